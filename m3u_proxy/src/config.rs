@@ -1,6 +1,14 @@
+use clap::Parser;
 use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::error::{AppError, Result};
+
+/// 默认配置文件路径（存在时自动加载）
+const DEFAULT_CONFIG_FILE: &str = "./config.toml";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     #[serde(default = "default_host")]
     pub host: String,
@@ -20,11 +28,184 @@ pub struct Config {
     #[serde(default = "default_cache_ttl_segment")]
     pub cache_ttl_segment: u64,
 
+    /// 视频片段缓存容量（MB），按片段字节数计算
+    #[serde(default = "default_cache_segment_capacity")]
+    pub cache_segment_capacity: u64,
+
+    /// 视频片段磁盘缓存目录，未配置时只使用内存缓存
+    #[serde(default)]
+    pub cache_disk_dir: Option<String>,
+
+    /// 磁盘缓存容量（MB），超出后按最近最少使用淘汰
+    #[serde(default = "default_cache_disk_capacity")]
+    pub cache_disk_capacity: u64,
+
     #[serde(default = "default_request_timeout")]
     pub request_timeout: u64,
 
+    /// 同时代理的播放列表和片段请求数上限，超出时返回 503
     #[serde(default = "default_max_concurrent")]
     pub max_concurrent: usize,
+
+    /// M3U 文件变化检查间隔（秒），0 表示关闭热重载
+    #[serde(default = "default_watch_interval")]
+    pub watch_interval: u64,
+
+    /// 远程订阅刷新间隔（秒），0 表示只在启动时拉取
+    #[serde(default = "default_refresh_interval")]
+    pub refresh_interval: u64,
+
+    /// 数据目录（保存远程订阅的本地副本等）
+    #[serde(default = "default_data_dir")]
+    pub data_dir: String,
+
+    /// 对外访问地址（如 `http://tv.example.com:8006`），用于生成导出播放列表中的绝对地址；
+    /// 未配置时根据请求的 Host 头推断
+    #[serde(default)]
+    pub public_url: Option<String>,
+
+    /// 多个命名来源，为空时使用 `m3u_path` 作为唯一来源
+    #[serde(default)]
+    pub sources: Vec<SourceConfig>,
+
+    /// 把 tvg-id 或名称相同的频道合并为一个频道，其余地址作为备用地址自动切换
+    #[serde(default = "default_failover_enabled")]
+    pub failover_enabled: bool,
+
+    /// 是否加载 EPG 节目单
+    #[serde(default = "default_epg_enabled")]
+    pub epg_enabled: bool,
+
+    /// XMLTV 地址或本地路径，为空时使用播放列表头部的 `x-tvg-url`
+    #[serde(default)]
+    pub epg_urls: Vec<String>,
+
+    /// EPG 刷新间隔（秒），0 表示只在启动时加载
+    #[serde(default = "default_epg_refresh_interval")]
+    pub epg_refresh_interval: u64,
+
+    /// 导出的 EPG（`/epg.xml`）包含当前时间之前多少秒内的节目
+    #[serde(default = "default_epg_export_past")]
+    pub epg_export_past: u64,
+
+    /// 导出的 EPG（`/epg.xml`）包含当前时间之后多少秒内的节目
+    #[serde(default = "default_epg_export_future")]
+    pub epg_export_future: u64,
+
+    /// 频道健康检查间隔（秒），0 表示关闭
+    #[serde(default = "default_health_check_interval")]
+    pub health_check_interval: u64,
+
+    /// 同时进行健康检查的频道数
+    #[serde(default = "default_health_check_concurrency")]
+    pub health_check_concurrency: usize,
+
+    /// 单个频道的健康检查时限（秒）
+    #[serde(default = "default_health_check_timeout")]
+    pub health_check_timeout: u64,
+
+    /// 代理接口的 SSRF 防护：只允许代理频道地址所在的主机，拒绝内网地址和非 HTTP 协议
+    #[serde(default = "default_ssrf_protection")]
+    pub ssrf_protection: bool,
+
+    /// 额外允许代理的主机（`host` 或 `host:port`），例如频道地址之外的 CDN
+    #[serde(default)]
+    pub proxy_allowed_hosts: Vec<String>,
+
+    /// 允许代理的端口（频道地址本身的端口总是允许）
+    #[serde(default = "default_proxy_allowed_ports")]
+    pub proxy_allowed_ports: Vec<u16>,
+
+    /// 代理令牌的签名密钥，未配置时使用数据目录下自动生成的 `proxy_token.key`
+    #[serde(default)]
+    pub proxy_token_secret: Option<String>,
+
+    /// 代理令牌有效期（秒），播放器会在整个观看过程中复用同一个播放列表地址
+    #[serde(default = "default_proxy_token_ttl")]
+    pub proxy_token_ttl: u64,
+
+    /// 代理令牌只对签发时的客户端 IP 有效（经反向代理访问时所有客户端的地址相同）
+    #[serde(default)]
+    pub proxy_token_bind_client: bool,
+
+    /// 导出链接令牌有效期（秒）：用户名密码登录的用户导出播放列表时，链接中附带的令牌代替密码；
+    /// 需要提前作废时修改用户的 `link_version`
+    #[serde(default = "default_link_token_ttl")]
+    pub link_token_ttl: u64,
+
+    /// API Key，配置了 API Key 或用户后除 `/health` 外的接口都需要认证
+    #[serde(default)]
+    pub api_keys: Vec<ApiKeyConfig>,
+
+    /// 使用用户名和密码（HTTP Basic）登录的用户
+    #[serde(default)]
+    pub users: Vec<UserConfig>,
+
+    /// 调试模式：上游播放列表不可用时返回固定的测试播放列表（仅用于开发调试）
+    #[serde(default)]
+    pub debug_mode: bool,
+}
+
+/// API Key，可通过 `X-API-Key` 或 `Authorization: Bearer` 请求头、或 `?token=` 参数提供
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKeyConfig {
+    /// 名称（记录在日志中）
+    pub name: String,
+
+    pub key: String,
+
+    /// 允许访问的分组（支持 `*`、`?` 通配符），与 `channels` 都为空时可以访问所有频道
+    #[serde(default)]
+    pub groups: Vec<String>,
+
+    /// 允许访问的频道，按频道 ID、tvg-id 或名称匹配（支持通配符）
+    #[serde(default)]
+    pub channels: Vec<String>,
+
+    /// 是否可以使用管理接口和查看来源信息，默认否
+    #[serde(default)]
+    pub admin: bool,
+}
+
+/// 用户
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserConfig {
+    pub name: String,
+
+    pub password: String,
+
+    /// 允许访问的分组（支持 `*`、`?` 通配符），与 `channels` 都为空时可以访问所有频道
+    #[serde(default)]
+    pub groups: Vec<String>,
+
+    /// 允许访问的频道，按频道 ID、tvg-id 或名称匹配（支持通配符）
+    #[serde(default)]
+    pub channels: Vec<String>,
+
+    /// 是否可以使用管理接口和查看来源信息，默认否
+    #[serde(default)]
+    pub admin: bool,
+
+    /// 导出链接令牌的版本，修改后该用户之前导出的链接全部失效
+    #[serde(default)]
+    pub link_version: u32,
+}
+
+/// 频道来源配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SourceConfig {
+    /// 来源名称（用于频道标记和本地副本文件名）
+    pub name: String,
+
+    /// 本地文件路径或 HTTP(S) 订阅地址
+    pub location: String,
+
+    /// 分组名称前缀
+    #[serde(default)]
+    pub group_prefix: Option<String>,
 }
 
 fn default_host() -> String {
@@ -51,6 +232,30 @@ fn default_cache_ttl_segment() -> u64 {
     600
 }
 
+fn default_cache_segment_capacity() -> u64 {
+    256
+}
+
+fn default_cache_disk_capacity() -> u64 {
+    4096
+}
+
+fn default_ssrf_protection() -> bool {
+    true
+}
+
+fn default_proxy_allowed_ports() -> Vec<u16> {
+    vec![80, 443, 8080, 8443]
+}
+
+fn default_proxy_token_ttl() -> u64 {
+    86400
+}
+
+fn default_link_token_ttl() -> u64 {
+    7 * 86400
+}
+
 fn default_request_timeout() -> u64 {
     30
 }
@@ -59,6 +264,50 @@ fn default_max_concurrent() -> usize {
     100
 }
 
+fn default_watch_interval() -> u64 {
+    5
+}
+
+fn default_refresh_interval() -> u64 {
+    3600
+}
+
+fn default_data_dir() -> String {
+    "./data".to_string()
+}
+
+fn default_failover_enabled() -> bool {
+    true
+}
+
+fn default_epg_enabled() -> bool {
+    true
+}
+
+fn default_epg_refresh_interval() -> u64 {
+    21600
+}
+
+fn default_epg_export_past() -> u64 {
+    7200
+}
+
+fn default_epg_export_future() -> u64 {
+    172800
+}
+
+fn default_health_check_interval() -> u64 {
+    1800
+}
+
+fn default_health_check_concurrency() -> usize {
+    10
+}
+
+fn default_health_check_timeout() -> u64 {
+    10
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
@@ -68,8 +317,636 @@ impl Default for Config {
             cache_enabled: default_cache_enabled(),
             cache_ttl_playlist: default_cache_ttl_playlist(),
             cache_ttl_segment: default_cache_ttl_segment(),
+            cache_segment_capacity: default_cache_segment_capacity(),
+            cache_disk_dir: None,
+            cache_disk_capacity: default_cache_disk_capacity(),
             request_timeout: default_request_timeout(),
             max_concurrent: default_max_concurrent(),
+            watch_interval: default_watch_interval(),
+            refresh_interval: default_refresh_interval(),
+            data_dir: default_data_dir(),
+            public_url: None,
+            sources: Vec::new(),
+            failover_enabled: default_failover_enabled(),
+            epg_enabled: default_epg_enabled(),
+            epg_urls: Vec::new(),
+            epg_refresh_interval: default_epg_refresh_interval(),
+            epg_export_past: default_epg_export_past(),
+            epg_export_future: default_epg_export_future(),
+            health_check_interval: default_health_check_interval(),
+            health_check_concurrency: default_health_check_concurrency(),
+            health_check_timeout: default_health_check_timeout(),
+            ssrf_protection: default_ssrf_protection(),
+            proxy_allowed_hosts: Vec::new(),
+            proxy_allowed_ports: default_proxy_allowed_ports(),
+            proxy_token_secret: None,
+            proxy_token_ttl: default_proxy_token_ttl(),
+            proxy_token_bind_client: false,
+            link_token_ttl: default_link_token_ttl(),
+            api_keys: Vec::new(),
+            users: Vec::new(),
+            debug_mode: false,
+        }
+    }
+}
+
+/// 命令行参数
+///
+/// 每个参数同时可以通过 `M3U_PROXY_<字段名>` 环境变量设置，命令行优先于环境变量
+#[derive(Debug, Default, Parser)]
+#[command(name = "m3u_proxy", version, about = "IPTV M3U 频道管理与 HLS 代理服务")]
+pub struct Cli {
+    /// 配置文件路径（TOML）
+    #[arg(short, long, env = "M3U_PROXY_CONFIG")]
+    pub config: Option<String>,
+
+    /// 监听地址
+    #[arg(long, env = "M3U_PROXY_HOST")]
+    pub host: Option<String>,
+
+    /// 监听端口
+    #[arg(short, long, env = "M3U_PROXY_PORT")]
+    pub port: Option<u16>,
+
+    /// M3U 文件路径或 HTTP(S) 订阅地址
+    #[arg(long, env = "M3U_PROXY_M3U_PATH")]
+    pub m3u_path: Option<String>,
+
+    /// 是否启用缓存
+    #[arg(long, env = "M3U_PROXY_CACHE_ENABLED")]
+    pub cache_enabled: Option<bool>,
+
+    /// 播放列表缓存时间（秒）
+    #[arg(long, env = "M3U_PROXY_CACHE_TTL_PLAYLIST")]
+    pub cache_ttl_playlist: Option<u64>,
+
+    /// 视频片段缓存时间（秒）
+    #[arg(long, env = "M3U_PROXY_CACHE_TTL_SEGMENT")]
+    pub cache_ttl_segment: Option<u64>,
+
+    /// 视频片段缓存容量（MB）
+    #[arg(long, env = "M3U_PROXY_CACHE_SEGMENT_CAPACITY")]
+    pub cache_segment_capacity: Option<u64>,
+
+    /// 视频片段磁盘缓存目录
+    #[arg(long, env = "M3U_PROXY_CACHE_DISK_DIR")]
+    pub cache_disk_dir: Option<String>,
+
+    /// 磁盘缓存容量（MB）
+    #[arg(long, env = "M3U_PROXY_CACHE_DISK_CAPACITY")]
+    pub cache_disk_capacity: Option<u64>,
+
+    /// 上游请求超时（秒）
+    #[arg(long, env = "M3U_PROXY_REQUEST_TIMEOUT")]
+    pub request_timeout: Option<u64>,
+
+    /// 同时代理的请求数上限
+    #[arg(long, env = "M3U_PROXY_MAX_CONCURRENT")]
+    pub max_concurrent: Option<usize>,
+
+    /// M3U 文件变化检查间隔（秒），0 表示关闭热重载
+    #[arg(long, env = "M3U_PROXY_WATCH_INTERVAL")]
+    pub watch_interval: Option<u64>,
+
+    /// 远程订阅刷新间隔（秒），0 表示只在启动时拉取
+    #[arg(long, env = "M3U_PROXY_REFRESH_INTERVAL")]
+    pub refresh_interval: Option<u64>,
+
+    /// 数据目录（保存远程订阅的本地副本等）
+    #[arg(long, env = "M3U_PROXY_DATA_DIR")]
+    pub data_dir: Option<String>,
+
+    /// 对外访问地址，用于生成导出播放列表中的绝对地址
+    #[arg(long, env = "M3U_PROXY_PUBLIC_URL")]
+    pub public_url: Option<String>,
+
+    /// 是否合并重复频道并在上游不可用时切换到备用地址
+    #[arg(long, env = "M3U_PROXY_FAILOVER_ENABLED")]
+    pub failover_enabled: Option<bool>,
+
+    /// 是否加载 EPG 节目单
+    #[arg(long, env = "M3U_PROXY_EPG_ENABLED")]
+    pub epg_enabled: Option<bool>,
+
+    /// XMLTV 地址或本地路径，可重复指定或用逗号分隔
+    #[arg(long = "epg-url", env = "M3U_PROXY_EPG_URLS", value_delimiter = ',')]
+    pub epg_urls: Option<Vec<String>>,
+
+    /// EPG 刷新间隔（秒），0 表示只在启动时加载
+    #[arg(long, env = "M3U_PROXY_EPG_REFRESH_INTERVAL")]
+    pub epg_refresh_interval: Option<u64>,
+
+    /// 导出的 EPG 包含当前时间之前多少秒内的节目
+    #[arg(long, env = "M3U_PROXY_EPG_EXPORT_PAST")]
+    pub epg_export_past: Option<u64>,
+
+    /// 导出的 EPG 包含当前时间之后多少秒内的节目
+    #[arg(long, env = "M3U_PROXY_EPG_EXPORT_FUTURE")]
+    pub epg_export_future: Option<u64>,
+
+    /// 频道健康检查间隔（秒），0 表示关闭
+    #[arg(long, env = "M3U_PROXY_HEALTH_CHECK_INTERVAL")]
+    pub health_check_interval: Option<u64>,
+
+    /// 同时进行健康检查的频道数
+    #[arg(long, env = "M3U_PROXY_HEALTH_CHECK_CONCURRENCY")]
+    pub health_check_concurrency: Option<usize>,
+
+    /// 单个频道的健康检查时限（秒）
+    #[arg(long, env = "M3U_PROXY_HEALTH_CHECK_TIMEOUT")]
+    pub health_check_timeout: Option<u64>,
+
+    /// 是否启用代理接口的 SSRF 防护
+    #[arg(long, env = "M3U_PROXY_SSRF_PROTECTION")]
+    pub ssrf_protection: Option<bool>,
+
+    /// 额外允许代理的主机（可重复，环境变量用逗号分隔）
+    #[arg(long = "proxy-allowed-host", env = "M3U_PROXY_PROXY_ALLOWED_HOSTS", value_delimiter = ',')]
+    pub proxy_allowed_hosts: Option<Vec<String>>,
+
+    /// 允许代理的端口（可重复，环境变量用逗号分隔）
+    #[arg(long = "proxy-allowed-port", env = "M3U_PROXY_PROXY_ALLOWED_PORTS", value_delimiter = ',')]
+    pub proxy_allowed_ports: Option<Vec<u16>>,
+
+    /// 代理令牌的签名密钥
+    #[arg(long, env = "M3U_PROXY_PROXY_TOKEN_SECRET", hide_env_values = true)]
+    pub proxy_token_secret: Option<String>,
+
+    /// 代理令牌有效期（秒）
+    #[arg(long, env = "M3U_PROXY_PROXY_TOKEN_TTL")]
+    pub proxy_token_ttl: Option<u64>,
+
+    /// 代理令牌是否绑定客户端 IP
+    #[arg(long, env = "M3U_PROXY_PROXY_TOKEN_BIND_CLIENT")]
+    pub proxy_token_bind_client: Option<bool>,
+
+    /// 导出链接令牌有效期（秒）
+    #[arg(long, env = "M3U_PROXY_LINK_TOKEN_TTL")]
+    pub link_token_ttl: Option<u64>,
+
+    /// API Key，格式为 `名称:key`（可重复，环境变量用逗号分隔），访问范围和管理权限只能在配置文件中设置
+    #[arg(long = "api-key", env = "M3U_PROXY_API_KEYS", value_delimiter = ',', hide_env_values = true)]
+    pub api_keys: Option<Vec<String>>,
+
+    /// 用户，格式为 `用户名:密码`（可重复，环境变量用逗号分隔），访问范围和管理权限只能在配置文件中设置
+    #[arg(long = "user", env = "M3U_PROXY_USERS", value_delimiter = ',', hide_env_values = true)]
+    pub users: Option<Vec<String>>,
+
+    /// 调试模式：上游播放列表不可用时返回固定的测试播放列表
+    #[arg(long, env = "M3U_PROXY_DEBUG_MODE")]
+    pub debug_mode: Option<bool>,
+}
+
+impl Config {
+    /// 按层级加载配置：默认值 -> 配置文件 -> 环境变量 -> 命令行参数
+    pub fn load(cli: Cli) -> Result<Self> {
+        let mut config = match cli.config.as_deref() {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::from_file(DEFAULT_CONFIG_FILE)?
+            }
+            None => Self::default(),
+        };
+
+        config.apply_cli(cli);
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// 从 TOML 文件读取配置，缺省字段使用默认值
+    pub fn from_file(path: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            AppError::Config(format!("Failed to read config file {}: {}", path, e))
+        })?;
+
+        toml::from_str(&content)
+            .map_err(|e| AppError::Config(format!("Invalid config file {}: {}", path, e)))
+    }
+
+    /// 用命令行参数（含环境变量）覆盖配置
+    fn apply_cli(&mut self, cli: Cli) {
+        if let Some(host) = cli.host {
+            self.host = host;
+        }
+        if let Some(port) = cli.port {
+            self.port = port;
+        }
+        if let Some(m3u_path) = cli.m3u_path {
+            self.m3u_path = m3u_path;
+        }
+        if let Some(cache_enabled) = cli.cache_enabled {
+            self.cache_enabled = cache_enabled;
+        }
+        if let Some(ttl) = cli.cache_ttl_playlist {
+            self.cache_ttl_playlist = ttl;
+        }
+        if let Some(ttl) = cli.cache_ttl_segment {
+            self.cache_ttl_segment = ttl;
+        }
+        if let Some(capacity) = cli.cache_segment_capacity {
+            self.cache_segment_capacity = capacity;
+        }
+        if let Some(dir) = cli.cache_disk_dir {
+            self.cache_disk_dir = Some(dir);
+        }
+        if let Some(capacity) = cli.cache_disk_capacity {
+            self.cache_disk_capacity = capacity;
+        }
+        if let Some(timeout) = cli.request_timeout {
+            self.request_timeout = timeout;
+        }
+        if let Some(max_concurrent) = cli.max_concurrent {
+            self.max_concurrent = max_concurrent;
+        }
+        if let Some(interval) = cli.watch_interval {
+            self.watch_interval = interval;
+        }
+        if let Some(interval) = cli.refresh_interval {
+            self.refresh_interval = interval;
+        }
+        if let Some(data_dir) = cli.data_dir {
+            self.data_dir = data_dir;
+        }
+        if let Some(public_url) = cli.public_url {
+            self.public_url = Some(public_url);
+        }
+        if let Some(failover_enabled) = cli.failover_enabled {
+            self.failover_enabled = failover_enabled;
+        }
+        if let Some(epg_enabled) = cli.epg_enabled {
+            self.epg_enabled = epg_enabled;
+        }
+        if let Some(epg_urls) = cli.epg_urls {
+            self.epg_urls = epg_urls;
+        }
+        if let Some(interval) = cli.epg_refresh_interval {
+            self.epg_refresh_interval = interval;
+        }
+        if let Some(past) = cli.epg_export_past {
+            self.epg_export_past = past;
+        }
+        if let Some(future) = cli.epg_export_future {
+            self.epg_export_future = future;
+        }
+        if let Some(interval) = cli.health_check_interval {
+            self.health_check_interval = interval;
+        }
+        if let Some(concurrency) = cli.health_check_concurrency {
+            self.health_check_concurrency = concurrency;
+        }
+        if let Some(timeout) = cli.health_check_timeout {
+            self.health_check_timeout = timeout;
+        }
+        if let Some(ssrf_protection) = cli.ssrf_protection {
+            self.ssrf_protection = ssrf_protection;
+        }
+        if let Some(hosts) = cli.proxy_allowed_hosts {
+            self.proxy_allowed_hosts = hosts;
         }
+        if let Some(ports) = cli.proxy_allowed_ports {
+            self.proxy_allowed_ports = ports;
+        }
+        if let Some(secret) = cli.proxy_token_secret {
+            self.proxy_token_secret = Some(secret);
+        }
+        if let Some(ttl) = cli.proxy_token_ttl {
+            self.proxy_token_ttl = ttl;
+        }
+        if let Some(bind_client) = cli.proxy_token_bind_client {
+            self.proxy_token_bind_client = bind_client;
+        }
+        if let Some(ttl) = cli.link_token_ttl {
+            self.link_token_ttl = ttl;
+        }
+        // 缺少 `:` 时名称为空，由 validate 报错
+        if let Some(api_keys) = cli.api_keys {
+            self.api_keys = api_keys
+                .iter()
+                .map(|entry| {
+                    let (name, key) = entry.split_once(':').unwrap_or(("", entry));
+                    ApiKeyConfig {
+                        name: name.to_string(),
+                        key: key.to_string(),
+                        groups: Vec::new(),
+                        channels: Vec::new(),
+                        admin: false,
+                    }
+                })
+                .collect();
+        }
+        if let Some(users) = cli.users {
+            self.users = users
+                .iter()
+                .map(|entry| {
+                    let (name, password) = entry.split_once(':').unwrap_or(("", entry));
+                    UserConfig {
+                        name: name.to_string(),
+                        password: password.to_string(),
+                        groups: Vec::new(),
+                        channels: Vec::new(),
+                        admin: false,
+                        link_version: 0,
+                    }
+                })
+                .collect();
+        }
+        if let Some(debug_mode) = cli.debug_mode {
+            self.debug_mode = debug_mode;
+        }
+    }
+
+    /// 实际生效的来源列表
+    pub fn effective_sources(&self) -> Vec<SourceConfig> {
+        if self.sources.is_empty() {
+            vec![SourceConfig {
+                name: "default".to_string(),
+                location: self.m3u_path.clone(),
+                group_prefix: None,
+            }]
+        } else {
+            self.sources.clone()
+        }
+    }
+
+    /// 校验配置值
+    pub fn validate(&self) -> Result<()> {
+        if self.host.trim().is_empty() {
+            return Err(AppError::Config("host must not be empty".to_string()));
+        }
+        if self.port == 0 {
+            return Err(AppError::Config("port must be between 1 and 65535".to_string()));
+        }
+        if self.m3u_path.trim().is_empty() {
+            return Err(AppError::Config("m3u_path must not be empty".to_string()));
+        }
+        for (i, source) in self.sources.iter().enumerate() {
+            let valid_name = !source.name.is_empty()
+                && source
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !valid_name {
+                return Err(AppError::Config(format!(
+                    "source name {:?} must be non-empty and contain only letters, digits, '-' or '_'",
+                    source.name
+                )));
+            }
+            if source.location.trim().is_empty() {
+                return Err(AppError::Config(format!(
+                    "source {} must have a location",
+                    source.name
+                )));
+            }
+            if self.sources[..i].iter().any(|s| s.name == source.name) {
+                return Err(AppError::Config(format!(
+                    "duplicate source name: {}",
+                    source.name
+                )));
+            }
+        }
+        if let Some(public_url) = &self.public_url
+            && !(public_url.starts_with("http://") || public_url.starts_with("https://"))
+        {
+            return Err(AppError::Config(format!(
+                "public_url must start with http:// or https://, got {}",
+                public_url
+            )));
+        }
+        if let Some(url) = self.epg_urls.iter().find(|u| u.trim().is_empty()) {
+            return Err(AppError::Config(format!(
+                "epg_urls contains an empty entry: {:?}",
+                url
+            )));
+        }
+        if self.epg_export_future == 0 {
+            return Err(AppError::Config(
+                "epg_export_future must be greater than 0".to_string(),
+            ));
+        }
+        if self.health_check_interval > 0
+            && (self.health_check_concurrency == 0 || self.health_check_timeout == 0)
+        {
+            return Err(AppError::Config(
+                "health_check_concurrency and health_check_timeout must be greater than 0 when health checks are enabled"
+                    .to_string(),
+            ));
+        }
+        if self.data_dir.trim().is_empty() {
+            return Err(AppError::Config("data_dir must not be empty".to_string()));
+        }
+        if self.request_timeout == 0 {
+            return Err(AppError::Config(
+                "request_timeout must be greater than 0".to_string(),
+            ));
+        }
+        if self.max_concurrent == 0 {
+            return Err(AppError::Config(
+                "max_concurrent must be greater than 0".to_string(),
+            ));
+        }
+        if self.cache_enabled && (self.cache_ttl_playlist == 0 || self.cache_ttl_segment == 0) {
+            return Err(AppError::Config(
+                "cache_ttl_playlist and cache_ttl_segment must be greater than 0 when cache is enabled"
+                    .to_string(),
+            ));
+        }
+        if self.cache_enabled && self.cache_segment_capacity == 0 {
+            return Err(AppError::Config(
+                "cache_segment_capacity must be greater than 0 when cache is enabled".to_string(),
+            ));
+        }
+        if let Some(dir) = &self.cache_disk_dir {
+            // 磁盘缓存是内存缓存的下一层，关闭缓存时不会使用
+            if !self.cache_enabled {
+                return Err(AppError::Config(
+                    "cache_disk_dir requires cache_enabled = true".to_string(),
+                ));
+            }
+            if dir.trim().is_empty() {
+                return Err(AppError::Config("cache_disk_dir must not be empty".to_string()));
+            }
+            if self.cache_disk_capacity == 0 {
+                return Err(AppError::Config(
+                    "cache_disk_capacity must be greater than 0".to_string(),
+                ));
+            }
+        }
+        if self.proxy_token_secret.as_ref().is_some_and(|s| s.trim().is_empty()) {
+            return Err(AppError::Config(
+                "proxy_token_secret must not be empty".to_string(),
+            ));
+        }
+        if self.proxy_token_ttl == 0 {
+            return Err(AppError::Config(
+                "proxy_token_ttl must be greater than 0".to_string(),
+            ));
+        }
+        if self.link_token_ttl == 0 {
+            return Err(AppError::Config(
+                "link_token_ttl must be greater than 0".to_string(),
+            ));
+        }
+        // API Key 和用户的名称共用一个命名空间，代理令牌中只记录名称
+        let credentials = self
+            .api_keys
+            .iter()
+            .map(|k| (&k.name, &k.key, &k.groups, &k.channels, k.admin))
+            .chain(
+                self.users
+                    .iter()
+                    .map(|u| (&u.name, &u.password, &u.groups, &u.channels, u.admin)),
+            );
+        let mut names = std::collections::HashSet::new();
+        for (name, secret, groups, channels, admin) in credentials {
+            if name.trim().is_empty() || secret.is_empty() {
+                return Err(AppError::Config(
+                    "api_keys and users must have a name and a non-empty key or password (name:secret)"
+                        .to_string(),
+                ));
+            }
+            if !names.insert(name) {
+                return Err(AppError::Config(format!(
+                    "duplicate api key or user name: {}",
+                    name
+                )));
+            }
+            if groups.iter().chain(channels).any(|p| p.trim().is_empty()) {
+                return Err(AppError::Config(format!(
+                    "{} has an empty group or channel pattern",
+                    name
+                )));
+            }
+            // 管理接口会暴露所有来源和频道，管理员不能只有部分频道的访问权限
+            if admin && !(groups.is_empty() && channels.is_empty()) {
+                return Err(AppError::Config(format!(
+                    "{} cannot be an admin while restricted to groups or channels",
+                    name
+                )));
+            }
+        }
+        for (i, api_key) in self.api_keys.iter().enumerate() {
+            if self.api_keys[..i].iter().any(|k| k.key == api_key.key) {
+                return Err(AppError::Config(format!(
+                    "api key {} reuses the key of another entry",
+                    api_key.name
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_env_names_match_fields() {
+        for arg in Cli::command().get_arguments() {
+            let Some(env) = arg.get_env() else {
+                continue;
+            };
+            let expected = format!("M3U_PROXY_{}", arg.get_id().as_str().to_uppercase());
+            assert_eq!(env.to_str(), Some(expected.as_str()));
+        }
+    }
+
+    #[test]
+    fn test_config_layers() {
+        let mut config: Config = toml::from_str(
+            r#"
+port = 9000
+m3u_path = "/data/playlist.m3u"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.host, "0.0.0.0");
+        assert_eq!(config.m3u_path, "/data/playlist.m3u");
+
+        config.apply_cli(Cli {
+            port: Some(9100),
+            ..Default::default()
+        });
+        assert_eq!(config.port, 9100);
+        assert_eq!(config.m3u_path, "/data/playlist.m3u");
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_config_rejects_invalid_values() {
+        assert!(toml::from_str::<Config>("port = 70000").is_err());
+        assert!(toml::from_str::<Config>("unknown_key = 1").is_err());
+
+        let config = Config {
+            request_timeout: 0,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        let config = Config {
+            cache_enabled: false,
+            cache_disk_dir: Some("/var/cache/m3u_proxy".to_string()),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        let config: Config = toml::from_str(
+            r#"
+[[sources]]
+name = "main"
+location = "./a.m3u"
+
+[[sources]]
+name = "main"
+location = "./b.m3u"
+"#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+
+        // API Key 和用户的名称不能重复，访问范围不能有空条目
+        let config: Config = toml::from_str(
+            r#"
+[[api_keys]]
+name = "team"
+key = "k-1"
+groups = ["体育"]
+
+[[users]]
+name = "team"
+password = "secret"
+"#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+
+        let config: Config = toml::from_str(
+            r#"
+[[api_keys]]
+name = "team"
+key = "k-1"
+channels = [""]
+"#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+
+        // 管理员不能限制访问范围
+        let config: Config = toml::from_str(
+            r#"
+[[users]]
+name = "ops"
+password = "secret"
+groups = ["体育"]
+admin = true
+"#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
     }
 }
@@ -1,9 +1,10 @@
 use axum::{
-    http::StatusCode,
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
 use serde_json::json;
+use std::sync::Arc;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -12,7 +13,7 @@ pub enum AppError {
     Io(#[from] std::io::Error),
 
     #[error("HTTP request error: {0}")]
-    Reqwest(#[from] reqwest::Error),
+    Reqwest(reqwest::Error),
 
     #[error("URL parse error: {0}")]
     UrlParse(#[from] url::ParseError),
@@ -23,29 +24,190 @@ pub enum AppError {
     #[error("Invalid M3U format: {0}")]
     InvalidM3U(String),
 
+    #[error("Bad request: {0}")]
+    BadRequest(String),
+
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("Invalid EPG data: {0}")]
+    InvalidEpg(String),
+
     #[error("Proxy error: {0}")]
     ProxyError(String),
 
+    /// 错误信息会返回给客户端，只包含主机名，完整地址只记录在服务端日志中
+    #[error("Upstream {host} returned {status}")]
+    UpstreamStatus {
+        status: u16,
+        host: String,
+        url: String,
+    },
+
+    #[error("Upstream {host} returned invalid content: {reason}")]
+    UpstreamInvalid { host: String, reason: String },
+
+    #[error("Upstream {host} timed out: {message}")]
+    UpstreamTimeout { host: String, message: String },
+
+    #[error("Service unavailable: {0}")]
+    Overloaded(String),
+
+    #[error("Config error: {0}")]
+    Config(String),
+
     #[error("Internal error: {0}")]
     Internal(String),
 }
 
+impl AppError {
+    /// 上游返回了非 2xx 状态
+    pub fn upstream_status(status: u16, url: &str) -> Self {
+        AppError::UpstreamStatus {
+            status,
+            host: origin_host(url),
+            url: url.to_string(),
+        }
+    }
+
+    /// 上游返回的内容无法使用（例如不是 M3U8）
+    pub fn upstream_invalid(url: &str, reason: impl Into<String>) -> Self {
+        AppError::UpstreamInvalid {
+            host: origin_host(url),
+            reason: reason.into(),
+        }
+    }
+
+    /// 上游错误对应的源站主机名，非上游错误返回 None
+    pub fn upstream_host(&self) -> Option<&str> {
+        match self {
+            AppError::UpstreamStatus { host, .. }
+            | AppError::UpstreamInvalid { host, .. }
+            | AppError::UpstreamTimeout { host, .. } => Some(host),
+            _ => None,
+        }
+    }
+}
+
+/// 错误信息会返回给客户端，去掉其中的上游地址
+impl From<reqwest::Error> for AppError {
+    fn from(error: reqwest::Error) -> Self {
+        AppError::Reqwest(error.without_url())
+    }
+}
+
+/// 合并的并发请求共享同一个错误，AppError 不可克隆，按类型重建
+impl From<Arc<AppError>> for AppError {
+    fn from(error: Arc<AppError>) -> Self {
+        Arc::try_unwrap(error).unwrap_or_else(|error| match &*error {
+            AppError::UpstreamStatus { status, host, url } => AppError::UpstreamStatus {
+                status: *status,
+                host: host.clone(),
+                url: url.clone(),
+            },
+            AppError::UpstreamInvalid { host, reason } => AppError::UpstreamInvalid {
+                host: host.clone(),
+                reason: reason.clone(),
+            },
+            AppError::UpstreamTimeout { host, message } => AppError::UpstreamTimeout {
+                host: host.clone(),
+                message: message.clone(),
+            },
+            AppError::ProxyError(message) => AppError::ProxyError(message.clone()),
+            AppError::Forbidden(message) => AppError::Forbidden(message.clone()),
+            other => AppError::ProxyError(other.to_string()),
+        })
+    }
+}
+
+/// 提取 URL 的主机名（含非默认端口），无法解析时返回原字符串
+pub fn origin_host(url: &str) -> String {
+    match url::Url::parse(url) {
+        Ok(parsed) => match (parsed.host_str(), parsed.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => url.to_string(),
+        },
+        Err(_) => url.to_string(),
+    }
+}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        if let AppError::UpstreamStatus { status, url, .. } = &self {
+            tracing::warn!("Upstream returned {} for {}", status, url);
+        }
+
         let (status, error_message) = match self {
             AppError::ChannelNotFound(_) => (StatusCode::NOT_FOUND, self.to_string()),
             AppError::InvalidM3U(_) => (StatusCode::BAD_REQUEST, self.to_string()),
             AppError::UrlParse(_) => (StatusCode::BAD_REQUEST, self.to_string()),
+            AppError::BadRequest(_) => (StatusCode::BAD_REQUEST, self.to_string()),
+            AppError::Unauthorized(_) => (StatusCode::UNAUTHORIZED, self.to_string()),
+            AppError::Forbidden(_) => (StatusCode::FORBIDDEN, self.to_string()),
             AppError::ProxyError(_) => (StatusCode::BAD_GATEWAY, self.to_string()),
+            // 上游 404 / 410 原样返回，其他源站错误（含 401、403、5xx）统一为 502，
+            // 4xx 只用于客户端自身的请求错误
+            AppError::UpstreamStatus { status: 404 | 410, .. } => {
+                (StatusCode::NOT_FOUND, self.to_string())
+            }
+            AppError::UpstreamStatus { .. } => (StatusCode::BAD_GATEWAY, self.to_string()),
+            AppError::UpstreamInvalid { .. } => (StatusCode::BAD_GATEWAY, self.to_string()),
+            AppError::UpstreamTimeout { .. } => (StatusCode::GATEWAY_TIMEOUT, self.to_string()),
+            AppError::Overloaded(_) => (StatusCode::SERVICE_UNAVAILABLE, self.to_string()),
             _ => (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()),
         };
 
-        let body = Json(json!({
+        let mut body = json!({
             "error": error_message,
-        }));
+        });
+        if let Some(host) = self.upstream_host() {
+            body["upstream_host"] = json!(host);
+        }
+        if let AppError::UpstreamStatus { status, .. } = self {
+            body["upstream_status"] = json!(status);
+        }
 
-        (status, body).into_response()
+        // 浏览器收到 Basic 质询后会提示输入用户名和密码
+        if status == StatusCode::UNAUTHORIZED {
+            return (
+                status,
+                [(header::WWW_AUTHENTICATE, "Basic realm=\"m3u_proxy\"")],
+                Json(body),
+            )
+                .into_response();
+        }
+
+        (status, Json(body)).into_response()
     }
 }
 
 pub type Result<T> = std::result::Result<T, AppError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upstream_error_statuses() {
+        let forbidden = AppError::upstream_status(403, "http://cdn.example.com:8080/live.m3u8");
+        assert_eq!(forbidden.upstream_host(), Some("cdn.example.com:8080"));
+        assert_eq!(forbidden.into_response().status(), StatusCode::BAD_GATEWAY);
+
+        let missing = AppError::upstream_status(404, "https://example.com/a.m3u8");
+        assert_eq!(missing.upstream_host(), Some("example.com"));
+        // 返回给客户端的信息中没有上游地址
+        assert!(!missing.to_string().contains("a.m3u8"));
+        assert_eq!(missing.into_response().status(), StatusCode::NOT_FOUND);
+
+        let invalid = AppError::upstream_invalid("https://example.com/a.m3u8", "not M3U8");
+        assert_eq!(invalid.into_response().status(), StatusCode::BAD_GATEWAY);
+
+        let client = AppError::BadRequest("missing url".to_string());
+        assert_eq!(client.upstream_host(), None);
+        assert_eq!(client.into_response().status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,87 @@
+use axum::{extract::State, Json};
+use serde::Serialize;
+use std::sync::Arc;
+use tracing::info;
+
+use crate::{
+    error::Result,
+    models::ParseReport,
+    services::{proxy_cache::CacheStats, ChannelManager, M3uSource, ProxyService},
+};
+
+/// 管理接口状态
+#[derive(Clone)]
+pub struct AdminState {
+    pub channel_manager: Arc<ChannelManager>,
+    pub sources: Vec<Arc<M3uSource>>,
+    pub proxy: Arc<ProxyService>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReloadResponse {
+    pub status: &'static str,
+    pub total: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ParseReportResponse {
+    pub reports: Vec<ParseReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CacheStatsResponse {
+    pub enabled: bool,
+    #[serde(flatten)]
+    pub stats: Option<CacheStats>,
+}
+
+/// 手动重新加载所有来源
+///
+/// POST /api/admin/reload
+///
+/// 单个来源失败时保留其原有频道；只有一个来源时直接返回该错误
+pub async fn reload_channels(State(state): State<AdminState>) -> Result<Json<ReloadResponse>> {
+    info!("Manual reload requested for {} sources", state.sources.len());
+
+    let mut errors = Vec::new();
+    for source in &state.sources {
+        if let Err(e) = source.reload(&state.channel_manager).await {
+            if state.sources.len() == 1 {
+                return Err(e);
+            }
+            errors.push(format!("{}: {}", source.name(), e));
+        }
+    }
+
+    let status = if errors.is_empty() { "ok" } else { "partial" };
+    let total = state.channel_manager.get_channel_count();
+
+    Ok(Json(ReloadResponse {
+        status,
+        total,
+        errors,
+    }))
+}
+
+/// 获取各来源最近一次的 M3U 解析报告
+///
+/// GET /api/admin/parse-report
+///
+/// 列出被跳过或有问题的行及原因，便于维护播放列表
+pub async fn get_parse_report(State(state): State<AdminState>) -> Json<ParseReportResponse> {
+    let reports = state.channel_manager.get_parse_reports();
+    Json(ParseReportResponse { reports })
+}
+
+/// 获取片段缓存各层（内存、磁盘）的命中统计
+///
+/// GET /api/admin/cache-stats
+pub async fn get_cache_stats(State(state): State<AdminState>) -> Json<CacheStatsResponse> {
+    let stats = state.proxy.cache_stats().await;
+    Json(CacheStatsResponse {
+        enabled: stats.is_some(),
+        stats,
+    })
+}
@@ -0,0 +1,267 @@
+use axum::{
+    Extension,
+    extract::{ConnectInfo, Request, State},
+    http::Uri,
+    middleware::Next,
+    response::Response,
+};
+use std::{net::SocketAddr, sync::Arc};
+use tracing::{Instrument, info_span};
+
+use crate::{
+    error::AppError,
+    services::{Authenticator, ProxyTokens, auth::Identity},
+};
+
+/// 认证中间件状态
+#[derive(Clone)]
+pub struct AuthState {
+    pub auth: Arc<Authenticator>,
+    pub tokens: Arc<ProxyTokens>,
+}
+
+/// 读取查询参数
+fn query_param(uri: &Uri, name: &str) -> Option<String> {
+    url::form_urlencoded::parse(uri.query()?.as_bytes())
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+}
+
+/// 接口认证
+///
+/// `?token=` 可以是 API Key，也可以是导出播放列表时签发的链接令牌。
+/// 认证通过后把调用方身份（`Identity`）放入请求扩展，并记录在之后的日志 span 中
+pub async fn require_auth(
+    State(state): State<AuthState>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let token = query_param(request.uri(), "token");
+    let link = match &token {
+        Some(token) => state.tokens.verify_link(token)?,
+        None => None,
+    };
+    let identity = match (link, &token) {
+        (Some(claims), Some(token)) => state.auth.link_identity(&claims, token)?,
+        _ => state
+            .auth
+            .authenticate(request.headers(), token.as_deref())?,
+    };
+
+    let span = info_span!("request", user = %identity.name);
+    request.extensions_mut().insert(identity);
+    Ok(next.run(request).instrument(span).await)
+}
+
+/// 管理权限
+///
+/// 放在 `require_auth` 之后，用于管理接口和来源信息等会暴露所有来源的接口，非管理员返回 403
+pub async fn require_admin(
+    Extension(identity): Extension<Identity>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    identity.check_admin()?;
+    Ok(next.run(request).await)
+}
+
+/// 代理接口认证
+///
+/// 播放器请求片段时不会附带 API Key，代理地址中的令牌就是凭据：令牌由已认证的接口签发并记录了调用方，
+/// 这里校验令牌（篡改、过期或不属于当前客户端时返回 403），把目标（`ProxyTarget`）和身份放入请求扩展
+pub async fn require_proxy_token(
+    State(state): State<AuthState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let token = query_param(request.uri(), "t")
+        .ok_or_else(|| AppError::Forbidden("Missing proxy token".to_string()))?;
+    let target = state.tokens.verify(&token, Some(addr.ip()))?;
+    let identity = state.auth.token_identity(target.subject.as_deref())?;
+
+    let span = info_span!("request", user = %identity.name);
+    request.extensions_mut().insert(target);
+    request.extensions_mut().insert(identity);
+    Ok(next.run(request).instrument(span).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ApiKeyConfig;
+    use crate::handlers::{
+        AdminState, AppState, get_cache_stats, get_channel_by_id, get_parse_report,
+        get_playlist_info, get_sources, reload_channels,
+    };
+    use crate::models::Channel;
+    use crate::services::{ChannelManager, EpgService, ProxyService};
+    use axum::{
+        Router,
+        body::Body,
+        http::{Method, StatusCode},
+        middleware,
+        routing::{get, post},
+    };
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+    use tower::Service;
+
+    fn api_key(name: &str, key: &str, groups: &[&str]) -> ApiKeyConfig {
+        ApiKeyConfig {
+            name: name.to_string(),
+            key: key.to_string(),
+            groups: groups.iter().map(|g| g.to_string()).collect(),
+            channels: Vec::new(),
+            admin: false,
+        }
+    }
+
+    fn app() -> Router {
+        let channel_manager = Arc::new(ChannelManager::new());
+        channel_manager.register_source("main", "./main.m3u");
+        channel_manager.set_source_header(
+            "main",
+            BTreeMap::from([(
+                "x-tvg-url".to_string(),
+                "http://epg.example.com".to_string(),
+            )]),
+        );
+        channel_manager
+            .update_source(
+                "main",
+                vec![Channel {
+                    id: "ch_1".to_string(),
+                    name: "CCTV1".to_string(),
+                    group: "央视".to_string(),
+                    url: "http://example.com/1.m3u8".to_string(),
+                    source: "main".to_string(),
+                    attributes: BTreeMap::from([
+                        ("tvg-chno".to_string(), "1".to_string()),
+                        ("tvg-rec".to_string(), "http://rec.example.com/1".to_string()),
+                    ]),
+                    vlc_options: BTreeMap::from([(
+                        "http-referrer".to_string(),
+                        "http://referrer.example.com/".to_string(),
+                    )]),
+                    kodi_props: BTreeMap::from([(
+                        "inputstream.adaptive.license_key".to_string(),
+                        "http://license.example.com/".to_string(),
+                    )]),
+                    ..Default::default()
+                }],
+            )
+            .unwrap();
+        let proxy = Arc::new(ProxyService::new(5).unwrap());
+
+        let state = AuthState {
+            auth: Arc::new(Authenticator::new(
+                &[
+                    ApiKeyConfig {
+                        admin: true,
+                        ..api_key("ops", "k-admin", &[])
+                    },
+                    api_key("all", "k-all", &[]),
+                    api_key("tv", "k-tv", &["体育"]),
+                ],
+                &[],
+            )),
+            tokens: Arc::new(ProxyTokens::new(b"secret", Duration::from_secs(60), false)),
+        };
+        let auth = middleware::from_fn_with_state(state, require_auth);
+        let admin = middleware::from_fn(require_admin);
+
+        let channel_routes = Router::new()
+            .route("/api/sources", get(get_sources).route_layer(admin.clone()))
+            .route("/api/playlist-info", get(get_playlist_info))
+            .route("/api/channels/:id", get(get_channel_by_id))
+            .route_layer(auth.clone())
+            .with_state(AppState {
+                channel_manager: channel_manager.clone(),
+                epg: Arc::new(EpgService::new(
+                    Vec::new(),
+                    channel_manager.clone(),
+                    proxy.clone(),
+                )),
+            });
+        let admin_routes = Router::new()
+            .route("/api/admin/reload", post(reload_channels))
+            .route("/api/admin/parse-report", get(get_parse_report))
+            .route("/api/admin/cache-stats", get(get_cache_stats))
+            .route_layer(admin)
+            .route_layer(auth)
+            .with_state(AdminState {
+                channel_manager,
+                sources: Vec::new(),
+                proxy,
+            });
+
+        channel_routes.merge(admin_routes)
+    }
+
+    async fn send(app: &mut Router, method: Method, path: &str, key: &str) -> (StatusCode, String) {
+        let request = Request::builder()
+            .method(method)
+            .uri(format!("{}?token={}", path, key))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.call(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    #[tokio::test]
+    async fn test_admin_routes_require_admin() {
+        let mut app = app();
+        let routes = [
+            (Method::POST, "/api/admin/reload"),
+            (Method::GET, "/api/admin/parse-report"),
+            (Method::GET, "/api/admin/cache-stats"),
+            (Method::GET, "/api/sources"),
+        ];
+
+        // 未设置 `admin = true` 的 key 不能使用管理接口，即使不限制访问范围
+        for key in ["k-tv", "k-all"] {
+            for (method, path) in routes.clone() {
+                let (status, _) = send(&mut app, method, path, key).await;
+                assert_eq!(status, StatusCode::FORBIDDEN, "{} {}", key, path);
+            }
+        }
+        for (method, path) in routes {
+            let (status, _) = send(&mut app, method, path, "k-admin").await;
+            assert_eq!(status, StatusCode::OK, "{}", path);
+        }
+
+        // 播放列表元数据只包含有可访问频道的来源
+        let (status, body) = send(&mut app, Method::GET, "/api/playlist-info", "k-tv").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!body.contains("epg.example.com"));
+        let (_, body) = send(&mut app, Method::GET, "/api/playlist-info", "k-admin").await;
+        assert!(body.contains("epg.example.com"));
+    }
+
+    #[tokio::test]
+    async fn test_channel_details_hidden_from_non_admins() {
+        let mut app = app();
+
+        let (status, body) = send(&mut app, Method::GET, "/api/channels/ch_1", "k-all").await;
+        assert_eq!(status, StatusCode::OK);
+        let channel: Channel = serde_json::from_str(&body).unwrap();
+        assert!(channel.url.is_empty());
+        assert!(channel.vlc_options.is_empty());
+        assert!(channel.kodi_props.is_empty());
+        assert_eq!(
+            channel.attributes,
+            BTreeMap::from([("tvg-chno".to_string(), "1".to_string())])
+        );
+        assert!(!body.contains("example.com"));
+
+        let (_, body) = send(&mut app, Method::GET, "/api/channels/ch_1", "k-admin").await;
+        let channel: Channel = serde_json::from_str(&body).unwrap();
+        assert_eq!(channel.url, "http://example.com/1.m3u8");
+        assert!(body.contains("license.example.com"));
+    }
+}
@@ -1,27 +1,59 @@
-use crate::error::Result;
-use crate::models::Channel;
-use crate::services::ChannelManager;
+use crate::error::{AppError, Result};
+use crate::models::{
+    Channel, EpgChannel, HealthStatus, NowNext, PlaylistInfo, Programme, SourceStatus,
+};
+use crate::services::auth::Identity;
+use crate::services::channel_manager::unix_now;
+use crate::services::{ChannelManager, EpgService};
 use axum::{
     extract::{Path, Query, State},
-    Json,
+    Extension, Json,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 
 #[derive(Clone)]
 pub struct AppState {
     pub channel_manager: std::sync::Arc<ChannelManager>,
+    pub epg: std::sync::Arc<EpgService>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct ChannelQuery {
     pub group: Option<String>,
     pub search: Option<String>,
+    /// 按健康检查状态筛选（online / offline / unknown）
+    pub status: Option<HealthStatus>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct ChannelsResponse {
     pub total: usize,
-    pub channels: Vec<Channel>,
+    pub channels: Vec<ChannelEntry>,
+}
+
+/// 频道列表中的单个频道，附带当前/下一个节目
+#[derive(Debug, Serialize)]
+pub struct ChannelEntry {
+    #[serde(flatten)]
+    pub channel: Channel,
+    pub epg: Option<NowNext>,
+}
+
+/// EPG 查询时间范围（Unix 时间戳，秒），默认从当前时间起 24 小时
+#[derive(Debug, Deserialize)]
+pub struct EpgQuery {
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChannelEpgResponse {
+    pub channel_id: String,
+    pub epg_channel: Option<EpgChannel>,
+    pub from: i64,
+    pub to: i64,
+    pub programmes: Vec<Programme>,
 }
 
 #[derive(Debug, Serialize)]
@@ -29,38 +61,151 @@ pub struct GroupsResponse {
     pub groups: Vec<String>,
 }
 
-/// 获取所有频道列表（支持分组和搜索过滤）
-pub async fn get_channels(
-    State(state): State<AppState>,
-    Query(query): Query<ChannelQuery>,
-) -> Result<Json<ChannelsResponse>> {
-    let channels = if let Some(group) = query.group {
+#[derive(Debug, Serialize)]
+pub struct SourcesResponse {
+    pub sources: Vec<SourceStatus>,
+}
+
+/// 按查询参数筛选频道，只保留调用方有权访问的频道
+pub(crate) fn filter_channels(
+    channel_manager: &ChannelManager,
+    query: &ChannelQuery,
+    identity: &Identity,
+) -> Vec<Channel> {
+    let mut channels = if let Some(group) = &query.group {
         // 按分组筛选
-        state.channel_manager.get_channels_by_group(&group)
-    } else if let Some(search) = query.search {
+        channel_manager.get_channels_by_group(group)
+    } else if let Some(search) = &query.search {
         // 按名称搜索
-        state.channel_manager.search_channels(&search)
+        channel_manager.search_channels(search)
     } else {
         // 获取所有频道
-        state.channel_manager.get_all_channels()
+        channel_manager.get_all_channels()
     };
 
+    // 按健康状态筛选
+    if let Some(status) = query.status {
+        channels.retain(|c| c.health_status() == status);
+    }
+
+    channels.retain(|c| identity.can_access(c));
+
+    channels
+}
+
+/// 上游地址和请求选项只返回给管理员，其他调用方通过 `/api/play` 获取代理后的播放地址
+fn visible(channel: Channel, identity: &Identity) -> Channel {
+    if identity.admin {
+        channel
+    } else {
+        channel.without_upstream_details()
+    }
+}
+
+/// 获取所有频道列表（支持分组和搜索过滤）
+pub async fn get_channels(
+    State(state): State<AppState>,
+    Extension(identity): Extension<Identity>,
+    Query(query): Query<ChannelQuery>,
+) -> Result<Json<ChannelsResponse>> {
+    let now = unix_now() as i64;
+    let channels: Vec<ChannelEntry> = filter_channels(&state.channel_manager, &query, &identity)
+        .into_iter()
+        .map(|channel| ChannelEntry {
+            epg: state.epg.now_next(&channel, now),
+            channel: visible(channel, &identity),
+        })
+        .collect();
+
     let total = channels.len();
 
     Ok(Json(ChannelsResponse { total, channels }))
 }
 
-/// 根据 ID 获取单个频道详情
+/// 根据 ID 获取单个频道详情，无权访问时返回 403
 pub async fn get_channel_by_id(
     State(state): State<AppState>,
+    Extension(identity): Extension<Identity>,
     Path(id): Path<String>,
 ) -> Result<Json<Channel>> {
     let channel = state.channel_manager.get_channel_by_id(&id)?;
-    Ok(Json(channel))
+    identity.check_access(&channel)?;
+    Ok(Json(visible(channel, &identity)))
 }
 
-/// 获取所有分组列表
-pub async fn get_groups(State(state): State<AppState>) -> Result<Json<GroupsResponse>> {
-    let groups = state.channel_manager.get_all_groups();
+/// 获取频道在指定时间范围内的节目单
+pub async fn get_channel_epg(
+    State(state): State<AppState>,
+    Extension(identity): Extension<Identity>,
+    Path(id): Path<String>,
+    Query(query): Query<EpgQuery>,
+) -> Result<Json<ChannelEpgResponse>> {
+    let channel = state.channel_manager.get_channel_by_id(&id)?;
+    identity.check_access(&channel)?;
+
+    let from = query.from.unwrap_or_else(|| unix_now() as i64);
+    let to = query.to.unwrap_or(from.saturating_add(24 * 3600));
+    if to <= from {
+        return Err(AppError::BadRequest(format!(
+            "`to` ({}) must be greater than `from` ({})",
+            to, from
+        )));
+    }
+
+    let epg_channel = state.epg.epg_channel_id(&channel).map(|id| {
+        state.epg.get_epg_channel(&id).unwrap_or(EpgChannel {
+            id,
+            ..Default::default()
+        })
+    });
+
+    Ok(Json(ChannelEpgResponse {
+        channel_id: channel.id.clone(),
+        epg_channel,
+        from,
+        to,
+        programmes: state.epg.get_programmes(&channel, from, to),
+    }))
+}
+
+/// 获取所有分组列表（只包含调用方有权访问的频道所在的分组）
+pub async fn get_groups(
+    State(state): State<AppState>,
+    Extension(identity): Extension<Identity>,
+) -> Result<Json<GroupsResponse>> {
+    let mut groups = state.channel_manager.get_all_groups();
+    if !identity.entitlements.is_unrestricted() {
+        let allowed: HashSet<String> = state
+            .channel_manager
+            .get_all_channels()
+            .into_iter()
+            .filter(|c| identity.can_access(c))
+            .map(|c| c.group)
+            .collect();
+        groups.retain(|group| allowed.contains(group));
+    }
     Ok(Json(GroupsResponse { groups }))
 }
+
+/// 获取所有来源的加载状态（频道数、最近加载时间、最近错误），仅管理员可用
+pub async fn get_sources(State(state): State<AppState>) -> Result<Json<SourcesResponse>> {
+    let sources = state.channel_manager.get_source_statuses();
+    Ok(Json(SourcesResponse { sources }))
+}
+
+/// 获取播放列表元数据（x-tvg-url 等 `#EXTM3U` 头部属性）
+///
+/// 非管理员只能看到有可访问频道的来源
+pub async fn get_playlist_info(
+    State(state): State<AppState>,
+    Extension(identity): Extension<Identity>,
+) -> Result<Json<PlaylistInfo>> {
+    let info = if identity.admin {
+        state.channel_manager.get_playlist_info()
+    } else {
+        state
+            .channel_manager
+            .get_playlist_info_for(|channel| identity.can_access(channel))
+    };
+    Ok(Json(info))
+}
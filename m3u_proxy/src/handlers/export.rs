@@ -0,0 +1,190 @@
+use axum::{
+    extract::{Query, State},
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use flate2::{write::GzEncoder, Compression};
+use std::io::Write;
+use std::sync::Arc;
+use tracing::info;
+
+use super::channel::{filter_channels, ChannelQuery};
+use crate::error::{AppError, Result};
+use crate::services::auth::{Credential, Identity};
+use crate::services::channel_manager::unix_now;
+use crate::services::{ChannelManager, EpgService, M3uWriter, ProxyTokens, XmltvWriter};
+
+/// 播放列表导出状态
+#[derive(Clone)]
+pub struct ExportState {
+    pub channel_manager: Arc<ChannelManager>,
+    pub epg: Arc<EpgService>,
+    /// 为用户名密码登录的用户签发链接令牌
+    pub tokens: Arc<ProxyTokens>,
+    /// 对外访问地址，未配置时根据请求头推断
+    pub public_url: Option<String>,
+    /// 是否启用 EPG；启用时导出的播放列表指向本服务的 `/epg.xml`
+    pub epg_enabled: bool,
+    /// 导出 EPG 的时间窗口（当前时间之前 / 之后的秒数）
+    pub epg_export_past: u64,
+    pub epg_export_future: u64,
+}
+
+/// 推断客户端访问本服务使用的地址
+fn base_url(public_url: Option<&str>, headers: &HeaderMap) -> String {
+    if let Some(public_url) = public_url {
+        return public_url.trim_end_matches('/').to_string();
+    }
+
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    let proto = header("x-forwarded-proto").unwrap_or("http");
+
+    match header("x-forwarded-host").or_else(|| header("host")) {
+        Some(host) => format!("{}://{}", proto, host),
+        None => String::new(),
+    }
+}
+
+/// 导出 M3U 播放列表
+///
+/// GET /playlist.m3u?group={group}&search={keyword}
+///
+/// 支持与 `/api/channels` 相同的筛选参数，只包含调用方有权访问的频道，频道地址指向本代理的播放接口。
+/// 启用 EPG 时 tvg-id 替换为频道的稳定 ID，x-tvg-url 指向本服务的 `/epg.xml`，并去掉 tvg-shift。
+/// 启用认证时，播放、回看和节目单地址都附带同一个 `token`，播放器可以直接使用：
+/// 使用 API Key 导出时为该 key，用户名密码登录时为新签发的链接令牌（有效期见 `link_token_ttl`）
+pub async fn export_playlist(
+    State(state): State<ExportState>,
+    Extension(identity): Extension<Identity>,
+    Query(query): Query<ChannelQuery>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let mut channels = filter_channels(&state.channel_manager, &query, &identity);
+    let mut header = state.channel_manager.get_playlist_header();
+    let base_url = base_url(state.public_url.as_deref(), &headers);
+    let issued = match identity.credential {
+        Credential::Password => Some(state
+            .tokens
+            .issue_link(&identity.name, identity.link_version)?),
+        _ => None,
+    };
+    let token = issued.as_deref().or(identity.link_token());
+    let token_param = token.map(|token| format!("token={}", urlencoding::encode(token)));
+
+    // 回看请求也经过本服务，由 `/api/play/{id}/stream` 展开上游模板
+    for channel in channels.iter_mut().filter(|c| c.catchup.is_some()) {
+        channel
+            .attributes
+            .insert("catchup".to_string(), "default".to_string());
+        let mut source = format!(
+            "{}/api/play/{}/stream?start={{utc}}&duration={{duration}}",
+            base_url,
+            urlencoding::encode(&channel.id)
+        );
+        if let Some(param) = &token_param {
+            source.push('&');
+            source.push_str(param);
+        }
+        channel
+            .attributes
+            .insert("catchup-source".to_string(), source);
+    }
+
+    if state.epg_enabled {
+        // `/epg.xml` 中的时间已经按 tvg-shift 平移，不再让播放器重复平移
+        header.remove("url-tvg");
+        header.remove("tvg-shift");
+        let mut epg_url = format!("{}/epg.xml", base_url);
+        if let Some(param) = &token_param {
+            epg_url.push('?');
+            epg_url.push_str(param);
+        }
+        header.insert("x-tvg-url".to_string(), epg_url);
+        for channel in &mut channels {
+            channel.tvg_id = channel.id.clone();
+            channel.attributes.remove("tvg-shift");
+        }
+    }
+
+    info!("Exporting {} channels as M3U playlist", channels.len());
+
+    let playlist = M3uWriter::write(&header, &channels, &base_url, token);
+
+    Ok((
+        [
+            ("content-type", "audio/x-mpegurl; charset=utf-8"),
+            ("content-disposition", "inline; filename=\"playlist.m3u\""),
+            ("cache-control", "no-cache"),
+        ],
+        playlist,
+    )
+        .into_response())
+}
+
+/// 生成当前频道目录（调用方有权访问的部分）对应的 XMLTV
+fn render_epg(state: &ExportState, query: &ChannelQuery, identity: &Identity) -> String {
+    let channels = filter_channels(&state.channel_manager, query, identity);
+    let now = unix_now() as i64;
+    let from = now.saturating_sub_unsigned(state.epg_export_past);
+    let to = now.saturating_add_unsigned(state.epg_export_future);
+
+    let guides = state.epg.guide(&channels, from, to);
+    info!(
+        "Exporting EPG for {} of {} channels",
+        guides.len(),
+        channels.len()
+    );
+
+    XmltvWriter::write(&guides)
+}
+
+/// 导出 XMLTV 节目单
+///
+/// GET /epg.xml?group={group}&search={keyword}
+///
+/// 只包含当前频道目录中匹配到节目单的频道，频道 ID 与 `/playlist.m3u` 中的 tvg-id 一致
+pub async fn export_epg(
+    State(state): State<ExportState>,
+    Extension(identity): Extension<Identity>,
+    Query(query): Query<ChannelQuery>,
+) -> Response {
+    let xml = render_epg(&state, &query, &identity);
+
+    (
+        [
+            ("content-type", "application/xml; charset=utf-8"),
+            ("content-disposition", "inline; filename=\"epg.xml\""),
+            ("cache-control", "no-cache"),
+        ],
+        xml,
+    )
+        .into_response()
+}
+
+/// 导出 gzip 压缩的 XMLTV 节目单
+///
+/// GET /epg.xml.gz?group={group}&search={keyword}
+pub async fn export_epg_gzip(
+    State(state): State<ExportState>,
+    Extension(identity): Extension<Identity>,
+    Query(query): Query<ChannelQuery>,
+) -> Result<Response> {
+    let xml = render_epg(&state, &query, &identity);
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(xml.as_bytes())?;
+    let compressed = encoder
+        .finish()
+        .map_err(|e| AppError::Internal(format!("Failed to compress EPG: {}", e)))?;
+
+    Ok((
+        [
+            ("content-type", "application/gzip"),
+            ("content-disposition", "attachment; filename=\"epg.xml.gz\""),
+            ("cache-control", "no-cache"),
+        ],
+        compressed,
+    )
+        .into_response())
+}
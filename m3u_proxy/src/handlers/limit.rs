@@ -0,0 +1,83 @@
+use axum::{
+    body::{Body, HttpBody},
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use futures_util::StreamExt;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+use crate::error::AppError;
+
+/// 代理请求并发限制（`max_concurrent`）
+///
+/// 流式响应的许可在响应体发送完毕或客户端断开后才释放，长时间的片段下载也计入并发数；
+/// 没有空闲许可时直接返回 503，不排队等待
+pub async fn limit_concurrency(
+    State(limit): State<Arc<Semaphore>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let permit = limit
+        .try_acquire_owned()
+        .map_err(|_| AppError::Overloaded("Too many concurrent proxy requests".to_string()))?;
+
+    let response = next.run(request).await;
+    // 已经在内存中的响应体（播放列表、缓存命中）长度已知，不再占用许可
+    if response.body().size_hint().exact().is_some() {
+        return Ok(response);
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = body.into_data_stream().map(move |chunk| {
+        let _ = &permit;
+        chunk
+    });
+    Ok(Response::from_parts(parts, Body::from_stream(body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, http::StatusCode, middleware, routing::get};
+    use tower::Service;
+
+    #[tokio::test]
+    async fn test_limit_concurrency() {
+        let limit = Arc::new(Semaphore::new(1));
+        let mut app = Router::new()
+            .route("/", get(|| async { "ok" }))
+            .route(
+                "/stream",
+                get(|| async {
+                    let chunks = futures_util::stream::iter([Ok::<_, std::io::Error>("ok")]);
+                    Body::from_stream(chunks)
+                }),
+            )
+            .route_layer(middleware::from_fn_with_state(
+                limit.clone(),
+                limit_concurrency,
+            ));
+        let request = |uri| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        // 长度已知的响应立即释放许可
+        let buffered = app.call(request("/")).await.unwrap();
+        assert_eq!(buffered.status(), StatusCode::OK);
+        assert_eq!(limit.available_permits(), 1);
+
+        // 流式响应体未读完之前许可不会释放
+        let first = app.call(request("/stream")).await.unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+        let second = app.call(request("/")).await.unwrap();
+        assert_eq!(second.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let body = axum::body::to_bytes(first.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, "ok");
+        assert_eq!(limit.available_permits(), 1);
+        let third = app.call(request("/stream")).await.unwrap();
+        assert_eq!(third.status(), StatusCode::OK);
+    }
+}
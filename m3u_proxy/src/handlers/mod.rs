@@ -1,9 +1,20 @@
+pub mod admin;
+pub mod auth;
 pub mod channel;
+pub mod export;
+pub mod limit;
 pub mod play;
 pub mod playlist;
 pub mod segment;
 
-pub use channel::{AppState, get_channels, get_channel_by_id, get_groups};
+pub use admin::{AdminState, get_cache_stats, get_parse_report, reload_channels};
+pub use auth::{AuthState, require_admin, require_auth, require_proxy_token};
+pub use channel::{
+    AppState, get_channel_by_id, get_channel_epg, get_channels, get_groups, get_playlist_info,
+    get_sources,
+};
+pub use export::{ExportState, export_epg, export_epg_gzip, export_playlist};
+pub use limit::limit_concurrency;
 pub use play::{PlayState, get_play_info, play_stream};
 pub use playlist::{PlaylistState, proxy_playlist};
 pub use segment::{SegmentState, proxy_segment};
@@ -1,32 +1,89 @@
 use axum::{
-    extract::{Path, State},
+    extract::{ConnectInfo, Path, Query, State},
     http::StatusCode,
     response::{IntoResponse, Response},
-    Json,
+    Extension, Json,
 };
+use serde::Deserialize;
 use serde_json::json;
-use std::sync::Arc;
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
 use tracing::info;
 
 use crate::{
     error::AppError,
-    services::{channel_manager::ChannelManager, m3u8_rewriter::M3u8Rewriter},
+    models::{Channel, StreamType},
+    services::{
+        auth::Identity,
+        channel_manager::{unix_now, ChannelManager},
+        m3u8_rewriter::M3u8Rewriter,
+        proxy_token::TokenScope,
+        CatchupResolver, EpgService,
+    },
 };
 
+/// 未指定回看时长且 EPG 中找不到对应节目时使用的默认时长（秒）
+const DEFAULT_CATCHUP_DURATION: i64 = 3600;
+
 /// 频道播放处理器状态
 #[derive(Clone)]
 pub struct PlayState {
     pub channel_manager: Arc<ChannelManager>,
+    pub epg: Arc<EpgService>,
     pub rewriter: Arc<M3u8Rewriter>,
 }
 
+/// 回看参数（Unix 时间戳和时长，秒）
+#[derive(Debug, Deserialize)]
+pub struct PlayQuery {
+    pub start: Option<i64>,
+    pub duration: Option<i64>,
+}
+
+/// 生成播放地址
+///
+/// HLS 走播放列表代理，其他 http(s) 流通过片段代理转发，上游地址不会出现在返回给客户端的地址中；
+/// 无法代理的协议（rtmp、rtsp、udp 等）只有管理员可以拿到原始地址
+fn play_url(
+    state: &PlayState,
+    channel: &Channel,
+    stream_url: &str,
+    identity: &Identity,
+    client: IpAddr,
+) -> Result<String, AppError> {
+    let scope = TokenScope {
+        channel_id: Some(&channel.id),
+        subject: identity.subject(),
+        client: Some(client),
+    };
+    if channel.stream_type == StreamType::HLS {
+        return state.rewriter.create_playlist_url(stream_url, &scope);
+    }
+
+    let scheme = stream_url
+        .split_once("://")
+        .map(|(scheme, _)| scheme.to_ascii_lowercase());
+    match scheme.as_deref() {
+        Some("http" | "https") => state.rewriter.create_proxy_url(stream_url, &scope),
+        _ if identity.admin => Ok(stream_url.to_string()),
+        _ => Err(AppError::Forbidden(format!(
+            "Channel {} uses a stream that cannot be proxied",
+            channel.id
+        ))),
+    }
+}
+
 /// 获取频道播放信息
 ///
 /// GET /api/play/{channel_id}
 ///
-/// 返回频道的播放信息，包括代理后的播放地址
+/// 返回频道的播放信息，包括代理后的播放地址（上游地址只返回给管理员）；调用方无权访问该频道时返回 403
 pub async fn get_play_info(
     State(state): State<PlayState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(identity): Extension<Identity>,
     Path(channel_id): Path<String>,
 ) -> Result<Response, AppError> {
     info!("Getting play info for channel: {}", channel_id);
@@ -35,41 +92,42 @@ pub async fn get_play_info(
     let channel = state
         .channel_manager
         .get_channel_by_id(&channel_id)?;
+    identity.check_access(&channel)?;
 
-    // 根据流类型返回不同的播放信息
-    let play_url = match channel.stream_type {
-        crate::models::channel::StreamType::HLS => {
-            // HLS 流需要通过代理，使用相对路径
-            let encoded_url = urlencoding::encode(&channel.url);
-            format!("/api/proxy/playlist?url={}", encoded_url)
-        }
-        _ => {
-            // 其他类型直接返回原始 URL
-            channel.url.clone()
-        }
-    };
+    // 主地址不可用时选择备用地址
+    let stream_url = state.channel_manager.select_stream_url(&channel);
 
-    let response = json!({
+    let play_url = play_url(&state, &channel, &stream_url, &identity, addr.ip())?;
+
+    let mut response = json!({
         "id": channel.id,
         "name": channel.name,
         "logo": channel.logo,
         "group": channel.group,
         "stream_type": format!("{:?}", channel.stream_type),
         "play_url": play_url,
-        "original_url": channel.url,
     });
+    if identity.admin {
+        response["original_url"] = json!(channel.url);
+        response["backup_urls"] = json!(channel.backup_urls);
+    }
 
     Ok(Json(response).into_response())
 }
 
 /// 直接播放频道（重定向到代理地址）
 ///
-/// GET /api/play/{channel_id}/stream
+/// GET /api/play/{channel_id}/stream?start={timestamp}&duration={seconds}
 ///
-/// 直接返回流数据，适合直接在 video 标签中使用
+/// 直接返回流数据，适合直接在 video 标签中使用。
+/// 指定 `start` 时按频道的 catchup 配置回看该时间段；未指定 `duration` 时使用 EPG 中
+/// 该时间点所在节目的剩余时长。调用方无权访问该频道时返回 403
 pub async fn play_stream(
     State(state): State<PlayState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(identity): Extension<Identity>,
     Path(channel_id): Path<String>,
+    Query(query): Query<PlayQuery>,
 ) -> Result<Response, AppError> {
     info!("Playing stream for channel: {}", channel_id);
 
@@ -77,27 +135,36 @@ pub async fn play_stream(
     let channel = state
         .channel_manager
         .get_channel_by_id(&channel_id)?;
+    identity.check_access(&channel)?;
 
-    // 根据流类型处理
-    match channel.stream_type {
-        crate::models::channel::StreamType::HLS => {
-            // HLS 流重定向到播放列表代理
-            let encoded_url = urlencoding::encode(&channel.url);
-            let redirect_url = format!("/api/proxy/playlist?url={}", encoded_url);
-
-            Ok((
-                StatusCode::TEMPORARY_REDIRECT,
-                [("Location", redirect_url.as_str())],
-            )
-                .into_response())
+    // 回看时展开 catchup-source 模板，否则播放直播地址
+    let stream_url = match query.start {
+        Some(start) => {
+            let now = unix_now() as i64;
+            // 先检查 `start`，之后的计算不会溢出
+            CatchupResolver::check_start(start, now)?;
+            let duration = query.duration.unwrap_or_else(|| {
+                state
+                    .epg
+                    .get_programmes(&channel, start, start + 1)
+                    .first()
+                    .map(|p| (p.stop - start).min(CatchupResolver::MAX_DURATION))
+                    .unwrap_or(DEFAULT_CATCHUP_DURATION)
+            });
+            let url = CatchupResolver::resolve(&channel, start, duration, now)?;
+            info!("Catch-up for channel {} from {} ({}s)", channel.id, start, duration);
+            url
         }
-        _ => {
-            // 其他类型重定向到原始 URL
-            Ok((
-                StatusCode::TEMPORARY_REDIRECT,
-                [("Location", channel.url.as_str())],
-            )
-                .into_response())
-        }
-    }
+        // 主地址不可用时选择备用地址
+        None => state.channel_manager.select_stream_url(&channel),
+    };
+
+    // 重定向到代理地址
+    let redirect_url = play_url(&state, &channel, &stream_url, &identity, addr.ip())?;
+
+    Ok((
+        StatusCode::TEMPORARY_REDIRECT,
+        [("Location", redirect_url.as_str())],
+    )
+        .into_response())
 }
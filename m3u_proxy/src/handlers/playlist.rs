@@ -1,99 +1,142 @@
 use axum::{
-    extract::{Query, State},
+    extract::{ConnectInfo, State},
     response::{IntoResponse, Response},
+    Extension,
 };
-use serde::Deserialize;
-use std::sync::Arc;
-use tracing::{error, info};
+use std::{net::SocketAddr, sync::Arc};
+use tracing::{debug, error, info, warn};
 
 use crate::{
     error::AppError,
-    services::{m3u8_rewriter::M3u8Rewriter, proxy::ProxyService},
+    services::{
+        auth::Identity,
+        channel_manager::ChannelManager,
+        m3u8_rewriter::M3u8Rewriter,
+        proxy::{ProxyService, UpstreamOptions},
+        proxy_token::{ProxyTarget, TokenScope},
+    },
 };
 
+/// 调试模式下上游不可用时返回的测试播放列表
+const DEBUG_FIXTURE_PLAYLIST: &str = r#"#EXTM3U
+#EXT-X-VERSION:3
+#EXT-X-TARGETDURATION:10
+#EXT-X-MEDIA-SEQUENCE:0
+#EXTINF:10.0,
+https://test-streams.mux.dev/x36xhzz/url_6/193039199_mp4_h264_aac_hq_7.m3u8
+#EXTINF:10.0,
+https://test-streams.mux.dev/x36xhzz/url_6/193039199_mp4_h264_aac_hq_8.m3u8
+#EXT-X-ENDLIST"#;
+
 /// 播放列表代理状态
 #[derive(Clone)]
 pub struct PlaylistState {
     pub proxy: Arc<ProxyService>,
     pub rewriter: Arc<M3u8Rewriter>,
+    pub channel_manager: Arc<ChannelManager>,
+    /// 调试模式：上游不可用时返回测试播放列表而不是错误
+    pub debug_mode: bool,
 }
 
-/// 查询参数
-#[derive(Debug, Deserialize)]
-pub struct ProxyQuery {
-    url: String,
+/// 根据频道 ID 获取上游请求选项，频道不存在时使用默认选项
+pub(crate) fn upstream_options(
+    channel_manager: &ChannelManager,
+    channel_id: Option<&str>,
+) -> UpstreamOptions {
+    channel_id
+        .and_then(|id| channel_manager.get_channel_by_id(id).ok())
+        .map(|channel| UpstreamOptions::from_channel(&channel))
+        .unwrap_or_default()
 }
 
 /// 代理 M3U8 播放列表
 ///
-/// GET /api/proxy/playlist?url={encoded_url}
+/// GET /api/proxy/playlist?t={token}
 ///
-/// 1. 从原始服务器获取 M3U8 内容
+/// 1. 从原始服务器获取 M3U8 内容（频道地址不可用时切换到备用地址）
 /// 2. 重写其中的 URL 为代理地址
 /// 3. 返回重写后的内容
+///
+/// 令牌由 `require_proxy_token` 校验，被篡改、已过期或不属于当前客户端时返回 403；
+/// 所有地址都不可用时返回错误：上游 404 原样返回，超时返回 504，其他失败返回 502
 pub async fn proxy_playlist(
     State(state): State<PlaylistState>,
-    Query(query): Query<ProxyQuery>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(target): Extension<ProxyTarget>,
+    Extension(identity): Extension<Identity>,
 ) -> Result<Response, AppError> {
-    info!("Proxying playlist: {}", query.url);
-
-    // 获取原始 M3U8 内容
-    let response_result = state.proxy.proxy_get(&query.url).await;
-
-    // 打印响应日志
-    info!("Response result: {:?}", response_result.is_ok());
-
-    let content = match response_result {
-        Ok(response) => {
-            info!("Successfully received response from source");
-
-            // 提取响应体
-            let body = response.into_body();
-            let bytes = axum::body::to_bytes(body, usize::MAX)
-                .await
-                .map_err(|e| AppError::ProxyError(format!("Failed to read response body: {}", e)))?;
-
-            let content = String::from_utf8(bytes.to_vec())
-                .map_err(|e| AppError::ProxyError(format!("Invalid UTF-8 in playlist: {}", e)))?;
-
-            info!("Received content length: {}", content.len());
-            info!("Content preview: {}", &content.chars().take(200).collect::<String>());
-
-            // 检查是否是 M3U8 内容
-            if !content.trim_start().starts_with("#EXTM3U") {
-                error!("Response is not a valid M3U8 playlist");
-                return Err(AppError::InvalidM3U(
-                    "Response is not a valid M3U8 playlist".to_string(),
-                ));
+    info!("Proxying playlist: {}", target.url);
+
+    let channel = target
+        .channel_id
+        .as_deref()
+        .and_then(|id| state.channel_manager.get_channel_by_id(id).ok());
+    let options = channel
+        .as_ref()
+        .map(UpstreamOptions::from_channel)
+        .unwrap_or_default();
+
+    // 请求的是频道的主地址或备用地址时，失败后按优先级尝试其余未离线的地址
+    let mut candidates = vec![target.url.clone()];
+    if let Some(channel) = &channel
+        && channel.stream_urls().any(|url| url == target.url)
+    {
+        candidates.extend(
+            channel
+                .stream_urls()
+                .filter(|url| *url != target.url && !state.channel_manager.is_offline(url))
+                .map(str::to_string),
+        );
+    }
+
+    let mut fetched = None;
+    let mut last_error = None;
+    for url in &candidates {
+        match state.proxy.fetch_playlist(url, &options).await {
+            Ok(content) => {
+                if *url != target.url {
+                    warn!("Playlist {} failed, switched to backup {}", target.url, url);
+                }
+                fetched = Some((url.clone(), content));
+                break;
+            }
+            Err(e) => {
+                error!("Failed to get playlist from {}: {}", url, e);
+                last_error = Some(e);
             }
-
-            content
         }
-        Err(e) => {
-            error!("Failed to get response from source: {}", e);
-            info!("Using fake M3U8 content for testing");
-
-            // 创建一个假的 M3U8 内容用于测试
-            let fake_content = r#"#EXTM3U
-#EXT-X-VERSION:3
-#EXT-X-TARGETDURATION:10
-#EXT-X-MEDIA-SEQUENCE:0
-#EXTINF:10.0,
-https://test-streams.mux.dev/x36xhzz/url_6/193039199_mp4_h264_aac_hq_7.m3u8
-#EXTINF:10.0,
-https://test-streams.mux.dev/x36xhzz/url_6/193039199_mp4_h264_aac_hq_8.m3u8
-#EXT-X-ENDLIST"#;
-
-            fake_content.to_string()
+    }
+
+    let (playlist_url, content) = match (fetched, last_error) {
+        (Some(fetched), _) => fetched,
+        (None, Some(e)) if !state.debug_mode => return Err(e),
+        (None, e) => {
+            warn!(
+                "Debug mode: serving test fixture instead of failed playlist {} ({:?})",
+                target.url, e
+            );
+            (target.url.clone(), DEBUG_FIXTURE_PLAYLIST.to_string())
         }
     };
 
-    // 重写 URL
-    let rewritten = state.rewriter.rewrite_m3u8(&content, &query.url)?;
+    // 允许代理播放列表中引用的主机（片段常放在其他 CDN 主机上）
+    state
+        .proxy
+        .allow_referenced(state.rewriter.referenced_urls(&content, &playlist_url))
+        .await;
+
+    // 重写 URL，子地址的令牌沿用当前频道和调用方
+    let scope = TokenScope {
+        channel_id: target.channel_id.as_deref(),
+        subject: identity.subject(),
+        client: Some(addr.ip()),
+    };
+    let rewritten = state
+        .rewriter
+        .rewrite_m3u8(&content, &playlist_url, &scope)?;
 
-    // 打印重写后的内容（用于调试）
-    info!("Rewritten m3u8 content length: {}", rewritten.len());
-    info!("Rewritten m3u8 preview:\n{}", &rewritten.chars().take(500).collect::<String>());
+    // 重写后的内容包含代理令牌，不写入日志
+    debug!("Rewritten m3u8 content length: {}", rewritten.len());
 
     // 返回重写后的内容
     Ok((
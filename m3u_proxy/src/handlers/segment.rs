@@ -1,38 +1,34 @@
-use axum::{
-    extract::{Query, State},
-    response::Response,
-};
-use serde::Deserialize;
+use axum::{extract::State, response::Response, Extension};
 use std::sync::Arc;
 use tracing::info;
 
-use crate::{error::AppError, services::proxy::ProxyService};
+use super::playlist::upstream_options;
+use crate::{
+    error::AppError,
+    services::{channel_manager::ChannelManager, proxy::ProxyService, proxy_token::ProxyTarget},
+};
 
 /// 视频片段代理状态
 #[derive(Clone)]
 pub struct SegmentState {
     pub proxy: Arc<ProxyService>,
-}
-
-/// 查询参数
-#[derive(Debug, Deserialize)]
-pub struct SegmentQuery {
-    url: String,
+    pub channel_manager: Arc<ChannelManager>,
 }
 
 /// 代理视频片段
 ///
-/// GET /api/proxy/segment?url={encoded_url}
+/// GET /api/proxy/segment?t={token}
 ///
-/// 直接代理 TS 视频片段或其他媒体文件
+/// 直接代理 TS 视频片段或其他媒体文件，令牌由 `require_proxy_token` 校验
 pub async fn proxy_segment(
     State(state): State<SegmentState>,
-    Query(query): Query<SegmentQuery>,
+    Extension(target): Extension<ProxyTarget>,
 ) -> Result<Response, AppError> {
-    info!("Proxying segment: {}", query.url);
+    info!("Proxying segment: {}", target.url);
 
     // 使用流式代理来处理视频片段
-    let response = state.proxy.proxy_stream(&query.url).await?;
+    let options = upstream_options(&state.channel_manager, target.channel_id.as_deref());
+    let response = state.proxy.proxy_stream(&target.url, &options).await?;
 
     Ok(response)
 }
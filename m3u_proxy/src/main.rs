@@ -4,14 +4,27 @@ mod handlers;
 mod models;
 mod services;
 
-use axum::{routing::get, Router};
-use config::Config;
+use axum::{
+    middleware,
+    routing::{get, post},
+    Router,
+};
+use clap::Parser;
+use config::{Cli, Config};
 use handlers::{
-    get_channel_by_id, get_channels, get_groups, get_play_info, play_stream, proxy_playlist,
-    proxy_segment, AppState, PlayState, PlaylistState, SegmentState,
+    export_epg, export_epg_gzip, export_playlist, get_cache_stats, get_channel_by_id,
+    get_channel_epg, get_channels, get_groups, get_parse_report, get_play_info,
+    get_playlist_info, get_sources, limit_concurrency, play_stream, proxy_playlist, proxy_segment,
+    reload_channels, require_admin, require_auth, require_proxy_token, AdminState, AppState, AuthState, ExportState, PlayState,
+    PlaylistState, SegmentState,
+};
+use services::{
+    spawn_epg_refresher, spawn_health_checker, spawn_m3u_refresher, spawn_m3u_watcher,
+    Authenticator, ChannelManager, DiskCache, EpgService, HealthChecker, M3u8Rewriter, M3uSource, ProxyCache,
+    ProxyService, ProxyTokens, UrlPolicy,
 };
-use services::{ChannelManager, M3u8Rewriter, ProxyService};
-use std::sync::Arc;
+use std::{net::SocketAddr, path::Path, sync::Arc, time::Duration};
+use tokio::sync::Semaphore;
 use tower_http::cors::{Any, CorsLayer};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
@@ -25,48 +38,221 @@ async fn main() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let config = Config::default();
-
-    // 初始化频道管理器并加载 M3U 文件
-    let channel_manager = Arc::new(ChannelManager::new());
-    match channel_manager.load_from_file(&config.m3u_path) {
-        Ok(count) => {
-            tracing::info!("Successfully loaded {} channels from {}", count, config.m3u_path);
-        }
+    let config = match Config::load(Cli::parse()) {
+        Ok(config) => config,
         Err(e) => {
-            tracing::error!("Failed to load M3U file: {}", e);
+            tracing::error!("Failed to load configuration: {}", e);
             std::process::exit(1);
         }
-    }
+    };
+
+    // 频道管理器（代理的主机白名单来自已加载的频道地址）
+    let channel_manager = Arc::new(ChannelManager::new().with_failover(config.failover_enabled));
 
     // 初始化代理服务
-    let proxy_service = Arc::new(
-        ProxyService::new(config.request_timeout).expect("Failed to create proxy service"),
+    let mut proxy_service =
+        ProxyService::new(config.request_timeout).expect("Failed to create proxy service");
+    if config.cache_enabled {
+        let mut cache = ProxyCache::new(
+            Duration::from_secs(config.cache_ttl_playlist),
+            Duration::from_secs(config.cache_ttl_segment),
+            config.cache_segment_capacity * 1024 * 1024,
+        );
+        if let Some(dir) = &config.cache_disk_dir {
+            let disk = DiskCache::open(
+                dir,
+                config.cache_disk_capacity * 1024 * 1024,
+                Duration::from_secs(config.cache_ttl_segment),
+            )
+            .expect("Failed to open disk cache");
+            cache = cache.with_disk(disk);
+        }
+        proxy_service = proxy_service.with_cache(cache);
+    }
+    if config.ssrf_protection {
+        let policy = UrlPolicy::new(
+            channel_manager.clone(),
+            &config.proxy_allowed_hosts,
+            &config.proxy_allowed_ports,
+        )
+        .with_trusted_urls(
+            config
+                .effective_sources()
+                .iter()
+                .map(|source| source.location.as_str())
+                .chain(config.epg_urls.iter().map(String::as_str)),
+        );
+        proxy_service = proxy_service
+            .with_url_policy(Arc::new(policy))
+            .expect("Failed to create proxy service");
+    } else {
+        tracing::warn!("SSRF protection is disabled: proxy endpoints can fetch any URL");
+    }
+    let proxy_service = Arc::new(proxy_service);
+
+    // 加载所有来源（本地路径或远程订阅）
+    let m3u_sources: Vec<Arc<M3uSource>> = config
+        .effective_sources()
+        .iter()
+        .map(|source| Arc::new(M3uSource::new(source, &config.data_dir, proxy_service.clone())))
+        .collect();
+
+    let mut loaded_sources = 0;
+    for source in &m3u_sources {
+        match source.load_initial(&channel_manager).await {
+            Ok(count) => {
+                loaded_sources += 1;
+                tracing::info!("Successfully loaded {} channels from {}", count, source.location());
+            }
+            Err(e) => {
+                tracing::error!("Failed to load source {}: {}", source.name(), e);
+            }
+        }
+    }
+    if loaded_sources == 0 {
+        tracing::error!("No M3U source could be loaded");
+        std::process::exit(1);
+    }
+    tracing::info!(
+        "Channel catalog ready: {} channels from {} sources",
+        channel_manager.get_channel_count(),
+        loaded_sources
+    );
+
+    for source in &m3u_sources {
+        if source.is_remote() {
+            // 定时刷新远程订阅
+            if config.refresh_interval > 0 {
+                spawn_m3u_refresher(
+                    channel_manager.clone(),
+                    source.clone(),
+                    Duration::from_secs(config.refresh_interval),
+                );
+            }
+        } else if config.watch_interval > 0 {
+            // 监听 M3U 文件变化，自动热重载
+            spawn_m3u_watcher(
+                channel_manager.clone(),
+                source.clone(),
+                Duration::from_secs(config.watch_interval),
+            );
+        }
+    }
+
+    // 初始化 EPG 服务，在后台加载节目单
+    let epg_service = Arc::new(EpgService::new(
+        config.epg_urls.clone(),
+        channel_manager.clone(),
+        proxy_service.clone(),
+    ));
+    if config.epg_enabled {
+        spawn_epg_refresher(
+            epg_service.clone(),
+            Duration::from_secs(config.epg_refresh_interval),
+        );
+    }
+
+    // 后台检查频道可用性
+    if config.health_check_interval > 0 {
+        let health_checker = Arc::new(HealthChecker::new(
+            proxy_service.clone(),
+            channel_manager.clone(),
+            config.health_check_concurrency,
+            Duration::from_secs(config.health_check_timeout),
+        ));
+        spawn_health_checker(
+            health_checker,
+            Duration::from_secs(config.health_check_interval),
+        );
+    }
+
+    // 代理令牌（代理地址中不暴露上游地址）
+    let token_secret = match &config.proxy_token_secret {
+        Some(secret) => secret.as_bytes().to_vec(),
+        None => services::proxy_token::load_or_create_secret(
+            Path::new(&config.data_dir).join("proxy_token.key"),
+        )
+        .expect("Failed to load proxy token key"),
+    };
+    let proxy_tokens = Arc::new(
+        ProxyTokens::new(
+            &token_secret,
+            Duration::from_secs(config.proxy_token_ttl),
+            config.proxy_token_bind_client,
+        )
+        .with_link_ttl(Duration::from_secs(config.link_token_ttl)),
     );
 
     // 初始化 M3U8 重写器
-    let proxy_base_url = format!("http://{}:{}", config.host, config.port);
-    let m3u8_rewriter = Arc::new(M3u8Rewriter::new(proxy_base_url));
+    let m3u8_rewriter = Arc::new(M3u8Rewriter::new(proxy_tokens.clone()));
 
     // 创建应用状态
     let channel_state = AppState {
         channel_manager: channel_manager.clone(),
+        epg: epg_service.clone(),
     };
 
     let play_state = PlayState {
         channel_manager: channel_manager.clone(),
+        epg: epg_service.clone(),
         rewriter: m3u8_rewriter.clone(),
     };
 
+    if config.debug_mode {
+        tracing::warn!("Debug mode enabled: failed playlists are replaced with a test fixture");
+    }
     let playlist_state = PlaylistState {
         proxy: proxy_service.clone(),
         rewriter: m3u8_rewriter.clone(),
+        channel_manager: channel_manager.clone(),
+        debug_mode: config.debug_mode,
     };
 
     let segment_state = SegmentState {
         proxy: proxy_service.clone(),
+        channel_manager: channel_manager.clone(),
+    };
+
+    let export_state = ExportState {
+        channel_manager: channel_manager.clone(),
+        epg: epg_service.clone(),
+        tokens: proxy_tokens.clone(),
+        public_url: config.public_url.clone(),
+        epg_enabled: config.epg_enabled,
+        epg_export_past: config.epg_export_past,
+        epg_export_future: config.epg_export_future,
+    };
+
+    let admin_state = AdminState {
+        channel_manager: channel_manager.clone(),
+        sources: m3u_sources.clone(),
+        proxy: proxy_service.clone(),
     };
 
+    // 认证：配置了 API Key 或用户后，除 /health 外的接口都需要凭据
+    let authenticator = Arc::new(Authenticator::new(&config.api_keys, &config.users));
+    if authenticator.is_enabled() {
+        tracing::info!(
+            "Authentication enabled: {} API keys, {} users",
+            config.api_keys.len(),
+            config.users.len()
+        );
+    } else {
+        tracing::warn!("Authentication is disabled: no api_keys or users configured");
+    }
+    let auth_state = AuthState {
+        auth: authenticator,
+        tokens: proxy_tokens.clone(),
+    };
+    let auth = middleware::from_fn_with_state(auth_state.clone(), require_auth);
+    let proxy_auth = middleware::from_fn_with_state(auth_state, require_proxy_token);
+    // 播放列表和片段代理共用一个并发限制
+    let proxy_limit = middleware::from_fn_with_state(
+        Arc::new(Semaphore::new(config.max_concurrent)),
+        limit_concurrency,
+    );
+    let admin = middleware::from_fn(require_admin);
+
     // 配置 CORS
     let cors = CorsLayer::new()
         .allow_origin(Any)
@@ -78,24 +264,53 @@ async fn main() {
     let channel_routes = Router::new()
         .route("/api/channels", get(get_channels))
         .route("/api/channels/:id", get(get_channel_by_id))
+        .route("/api/channels/:id/epg", get(get_channel_epg))
         .route("/api/groups", get(get_groups))
+        .route(
+            "/api/sources",
+            get(get_sources).route_layer(admin.clone()),
+        )
+        .route("/api/playlist-info", get(get_playlist_info))
+        .route_layer(auth.clone())
         .with_state(channel_state);
 
     // 播放路由
     let play_routes = Router::new()
         .route("/api/play/:id", get(get_play_info))
         .route("/api/play/:id/stream", get(play_stream))
+        .route_layer(auth.clone())
         .with_state(play_state);
 
-    // 代理路由
+    // 代理路由（代理地址中的令牌即凭据）
     let playlist_routes = Router::new()
         .route("/api/proxy/playlist", get(proxy_playlist))
+        .route_layer(proxy_limit.clone())
+        .route_layer(proxy_auth.clone())
         .with_state(playlist_state);
 
     let segment_routes = Router::new()
         .route("/api/proxy/segment", get(proxy_segment))
+        .route_layer(proxy_limit)
+        .route_layer(proxy_auth)
         .with_state(segment_state);
 
+    // 播放列表和节目单导出路由
+    let export_routes = Router::new()
+        .route("/playlist.m3u", get(export_playlist))
+        .route("/epg.xml", get(export_epg))
+        .route("/epg.xml.gz", get(export_epg_gzip))
+        .route_layer(auth.clone())
+        .with_state(export_state);
+
+    // 管理路由
+    let admin_routes = Router::new()
+        .route("/api/admin/reload", post(reload_channels))
+        .route("/api/admin/parse-report", get(get_parse_report))
+        .route("/api/admin/cache-stats", get(get_cache_stats))
+        .route_layer(admin)
+        .route_layer(auth)
+        .with_state(admin_state);
+
     // 合并所有路由
     let app = Router::new()
         .route("/health", get(health_check))
@@ -103,6 +318,8 @@ async fn main() {
         .merge(play_routes)
         .merge(playlist_routes)
         .merge(segment_routes)
+        .merge(export_routes)
+        .merge(admin_routes)
         .layer(cors);
 
     let addr = format!("{}:{}", config.host, config.port);
@@ -112,7 +329,8 @@ async fn main() {
         .await
         .expect("Failed to bind address");
 
-    axum::serve(listener, app)
+    // 代理令牌可以绑定客户端地址
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .expect("Server failed to start");
 }
@@ -1,26 +1,141 @@
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use super::{ChannelHealth, HealthStatus};
+
+/// 非管理员可见的 EXTINF 属性，其他属性可能包含上游地址
+const VIEWER_ATTRIBUTES: &[&str] = &[
+    "tvg-chno",
+    "tvg-shift",
+    "tvg-language",
+    "tvg-country",
+    "catchup",
+    "catchup-days",
+    "radio",
+];
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Channel {
     pub id: String,
     pub tvg_id: String,
+    #[serde(default)]
+    pub tvg_name: Option<String>,
     pub name: String,
     pub logo: Option<String>,
     pub group: String,
     pub url: String,
+    /// 备用地址（同一频道的其他上游，按优先级排列），主地址不可用时依次切换
+    #[serde(default)]
+    pub backup_urls: Vec<String>,
     pub stream_type: StreamType,
+    /// 所属来源名称
+    #[serde(default)]
+    pub source: String,
+    /// EXTINF 中的其他属性（tvg-chno、tvg-shift、catchup 等）
+    #[serde(default)]
+    pub attributes: BTreeMap<String, String>,
+    /// `#EXTVLCOPT` 选项（http-user-agent、http-referrer 等）
+    #[serde(default)]
+    pub vlc_options: BTreeMap<String, String>,
+    /// `#KODIPROP` 属性（inputstream、DRM 等）
+    #[serde(default)]
+    pub kodi_props: BTreeMap<String, String>,
+    /// 回看配置（catchup、catchup-days、catchup-source）
+    #[serde(default)]
+    pub catchup: Option<Catchup>,
+    /// 最近一次健康检查结果，尚未检查时为空
+    #[serde(default)]
+    pub health: Option<ChannelHealth>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
+#[allow(clippy::upper_case_acronyms)]
 pub enum StreamType {
     HLS,
     MP4,
     FLV,
+    #[default]
     Other,
 }
 
+/// 回看方式（`catchup` 属性）
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CatchupMode {
+    /// catchup-source 是完整的回看地址模板
+    Default,
+    /// 把 catchup-source 追加到直播地址之后
+    Append,
+    /// 在直播地址后追加 `utc={utc}&lutc={lutc}`
+    Shift,
+}
+
+/// 频道回看配置
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Catchup {
+    pub mode: CatchupMode,
+    /// 可回看的天数（`catchup-days`），未设置时不限制
+    pub days: Option<u32>,
+    /// 回看地址模板（`catchup-source`）
+    pub source: Option<String>,
+}
+
+impl Catchup {
+    /// 从频道属性解析回看配置
+    ///
+    /// 未设置 `catchup` 但有 `catchup-source` 时，完整 URL 按 default 处理，否则按 append 处理；
+    /// 不支持的回看方式（flussonic、xc 等）或缺少必需的模板时返回 None
+    pub fn from_attributes(attributes: &BTreeMap<String, String>) -> Option<Self> {
+        let source = attributes
+            .get("catchup-source")
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(str::to_string);
+
+        let mode = match attributes.get("catchup").map(|m| m.trim().to_lowercase()) {
+            Some(mode) if mode == "default" => CatchupMode::Default,
+            Some(mode) if mode == "append" => CatchupMode::Append,
+            Some(mode) if mode == "shift" || mode == "timeshift" => CatchupMode::Shift,
+            Some(mode) if !mode.is_empty() => return None,
+            _ => match &source {
+                Some(source) if source.contains("://") => CatchupMode::Default,
+                Some(_) => CatchupMode::Append,
+                None => return None,
+            },
+        };
+
+        if mode != CatchupMode::Shift && source.is_none() {
+            return None;
+        }
+
+        Some(Self {
+            mode,
+            days: attributes
+                .get("catchup-days")
+                .and_then(|d| d.trim().parse().ok()),
+            source,
+        })
+    }
+}
+
 impl Channel {
+    /// 根据稳定属性（来源、tvg-id、名称、URL）计算频道 ID
+    ///
+    /// 与频道在列表中的位置无关，插入或调整顺序不会改变已有频道的 ID
+    pub fn stable_id(namespace: &str, tvg_id: &str, name: &str, url: &str) -> String {
+        let mut hasher = Sha256::new();
+        for part in [namespace, tvg_id, name, url] {
+            hasher.update(part.as_bytes());
+            hasher.update([0u8]);
+        }
+
+        let digest = hasher.finalize();
+        let hex: String = digest[..6].iter().map(|b| format!("{:02x}", b)).collect();
+        format!("ch_{}", hex)
+    }
+
     pub fn detect_stream_type(url: &str) -> StreamType {
         let url_lower = url.to_lowercase();
         if url_lower.contains(".m3u8") || url_lower.contains("m3u8") {
@@ -33,4 +148,64 @@ impl Channel {
             StreamType::Other
         }
     }
+
+    /// 请求上游时使用的 User-Agent（`#EXTVLCOPT:http-user-agent` 或 EXTINF 属性）
+    pub fn user_agent(&self) -> Option<&str> {
+        self.option("http-user-agent")
+    }
+
+    /// 请求上游时使用的 Referer（兼容 `http-referrer` 和 `http-referer` 两种写法）
+    pub fn referrer(&self) -> Option<&str> {
+        self.option("http-referrer")
+            .or_else(|| self.option("http-referer"))
+    }
+
+    /// EPG 时间偏移（秒）
+    ///
+    /// 来自 `tvg-shift` 属性（单位为小时，可以是负数或小数），频道未设置时解析器会继承
+    /// `#EXTM3U` 头部的值；缺失或无法解析时为 0
+    pub fn tvg_shift(&self) -> i64 {
+        self.attributes
+            .get("tvg-shift")
+            .and_then(|v| v.trim().parse::<f64>().ok())
+            .filter(|hours| hours.is_finite() && hours.abs() <= 24.0)
+            .map(|hours| (hours * 3600.0).round() as i64)
+            .unwrap_or(0)
+    }
+
+    /// 主地址和备用地址（按优先级排列）
+    pub fn stream_urls(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.url.as_str()).chain(self.backup_urls.iter().map(String::as_str))
+    }
+
+    /// 去掉上游信息，用于返回给非管理员：主地址、备用地址、回看模板、
+    /// `#EXTVLCOPT` / `#KODIPROP`（请求头、DRM 授权地址等），属性只保留 `VIEWER_ATTRIBUTES`
+    pub fn without_upstream_details(mut self) -> Self {
+        self.url.clear();
+        self.backup_urls.clear();
+        self.vlc_options.clear();
+        self.kodi_props.clear();
+        self.attributes
+            .retain(|key, _| VIEWER_ATTRIBUTES.contains(&key.as_str()));
+        if let Some(catchup) = &mut self.catchup {
+            catchup.source = None;
+        }
+        self
+    }
+
+    /// 当前可用状态（尚未检查时为 Unknown）
+    pub fn health_status(&self) -> HealthStatus {
+        self.health
+            .as_ref()
+            .map(|h| h.status)
+            .unwrap_or(HealthStatus::Unknown)
+    }
+
+    fn option(&self, key: &str) -> Option<&str> {
+        self.vlc_options
+            .get(key)
+            .or_else(|| self.attributes.get(key))
+            .map(String::as_str)
+            .filter(|v| !v.is_empty())
+    }
 }
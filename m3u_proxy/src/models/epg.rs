@@ -0,0 +1,32 @@
+use serde::{Deserialize, Serialize};
+
+/// XMLTV 中的频道定义
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EpgChannel {
+    pub id: String,
+    pub display_names: Vec<String>,
+    pub icon: Option<String>,
+}
+
+/// 节目信息
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Programme {
+    /// XMLTV 频道 ID
+    pub channel: String,
+    /// 开始时间（Unix 时间戳，秒）
+    pub start: i64,
+    /// 结束时间（Unix 时间戳，秒）
+    pub stop: i64,
+    pub title: String,
+    pub sub_title: Option<String>,
+    pub description: Option<String>,
+    pub categories: Vec<String>,
+    pub icon: Option<String>,
+}
+
+/// 当前播出和下一个节目
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NowNext {
+    pub now: Option<Programme>,
+    pub next: Option<Programme>,
+}
@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+
+/// 频道可用状态
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Online,
+    Offline,
+    /// 尚未检查
+    Unknown,
+}
+
+/// 最近一次健康检查的结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelHealth {
+    pub status: HealthStatus,
+    /// 首个请求的响应耗时（毫秒），失败时为空
+    pub latency_ms: Option<u64>,
+    /// 检查时间（Unix 时间戳，秒）
+    pub last_checked: u64,
+    pub error: Option<String>,
+}
@@ -1,3 +1,13 @@
 pub mod channel;
+pub mod epg;
+pub mod health;
+pub mod parse_report;
+pub mod playlist;
+pub mod source;
 
-pub use channel::{Channel, StreamType};
+pub use channel::{Catchup, CatchupMode, Channel, StreamType};
+pub use epg::{EpgChannel, NowNext, Programme};
+pub use health::{ChannelHealth, HealthStatus};
+pub use parse_report::{ParseIssue, ParseIssueKind, ParseReport};
+pub use playlist::{PlaylistInfo, SourceHeader};
+pub use source::SourceStatus;
@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+
+/// M3U 解析问题类型
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ParseIssueKind {
+    /// 文件不以 `#EXTM3U` 开头
+    MissingHeader,
+    /// `#EXTINF` 之后没有 URL
+    MissingUrl,
+    /// URL 之前没有 `#EXTINF`
+    MissingExtinf,
+    /// `#EXTINF` 中没有频道名称
+    MissingName,
+    /// 无法解析的属性
+    InvalidAttribute,
+    /// 无法解析的 `#EXTVLCOPT` / `#KODIPROP` 指令
+    InvalidDirective,
+    /// 无法识别的 URL
+    InvalidUrl,
+    /// 与之前的频道 URL 重复
+    DuplicateUrl,
+}
+
+/// 单个解析问题
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParseIssue {
+    /// 行号（从 1 开始）
+    pub line: usize,
+    pub kind: ParseIssueKind,
+    pub message: String,
+    /// 该条目是否因此被跳过
+    pub skipped: bool,
+}
+
+/// 某个来源最近一次解析的报告
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParseReport {
+    pub source: String,
+    /// 解析时间（Unix 时间戳，秒）
+    pub parsed_at: u64,
+    pub channel_count: usize,
+    pub skipped_count: usize,
+    pub issues: Vec<ParseIssue>,
+}
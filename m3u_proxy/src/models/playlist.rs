@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// 播放列表级别的元数据（来自各来源的 `#EXTM3U` 行）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaylistInfo {
+    /// EPG 地址（x-tvg-url / url-tvg，已去重）
+    pub epg_urls: Vec<String>,
+    /// 合并后的头部属性
+    pub attributes: BTreeMap<String, String>,
+    /// 各来源原始的头部属性
+    pub sources: Vec<SourceHeader>,
+}
+
+/// 单个来源的头部属性
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceHeader {
+    pub name: String,
+    pub attributes: BTreeMap<String, String>,
+}
@@ -0,0 +1,12 @@
+use serde::{Deserialize, Serialize};
+
+/// 频道来源的加载状态
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceStatus {
+    pub name: String,
+    pub location: String,
+    pub channel_count: usize,
+    /// 最近一次成功加载的时间（Unix 时间戳，秒）
+    pub last_loaded_at: Option<u64>,
+    pub last_error: Option<String>,
+}
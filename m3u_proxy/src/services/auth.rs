@@ -0,0 +1,463 @@
+use axum::http::{HeaderMap, header};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::config::{ApiKeyConfig, UserConfig};
+use crate::error::{AppError, Result};
+use crate::models::Channel;
+use crate::services::proxy_token::LinkClaims;
+
+/// 调用方的认证方式
+#[derive(Debug, Clone, PartialEq)]
+pub enum Credential {
+    /// 未启用认证
+    Anonymous,
+    /// API Key，保留原值用于生成播放器可以直接使用的链接
+    ApiKey(String),
+    /// 用户名和密码
+    Password,
+    /// 代理地址中的令牌
+    ProxyToken,
+    /// 导出链接中的令牌（代替用户的密码），保留原值用于再次导出
+    LinkToken(String),
+}
+
+/// 可以访问的频道范围
+///
+/// 分组按名称匹配，频道按 ID、tvg-id 或名称匹配，都支持 `*`、`?` 通配符且不区分大小写；
+/// 两者都为空时不限制
+#[derive(Debug, Default)]
+pub struct Entitlements {
+    groups: Vec<String>,
+    channels: Vec<String>,
+}
+
+impl Entitlements {
+    pub fn new(groups: &[String], channels: &[String]) -> Self {
+        let lowercase = |patterns: &[String]| patterns.iter().map(|p| p.to_lowercase()).collect();
+        Self {
+            groups: lowercase(groups),
+            channels: lowercase(channels),
+        }
+    }
+
+    pub fn is_unrestricted(&self) -> bool {
+        self.groups.is_empty() && self.channels.is_empty()
+    }
+
+    /// 是否可以访问频道
+    pub fn allows(&self, channel: &Channel) -> bool {
+        if self.is_unrestricted() {
+            return true;
+        }
+
+        let group = channel.group.to_lowercase();
+        if self.groups.iter().any(|p| wildcard_match(p, &group)) {
+            return true;
+        }
+
+        let names = [&channel.id, &channel.tvg_id, &channel.name].map(|v| v.to_lowercase());
+        self.channels
+            .iter()
+            .any(|p| names.iter().any(|name| wildcard_match(p, name)))
+    }
+}
+
+/// 已认证的调用方
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub name: String,
+    pub credential: Credential,
+    pub entitlements: Arc<Entitlements>,
+    /// 是否可以使用管理接口和查看来源信息
+    pub admin: bool,
+    /// 签发导出链接令牌时写入的版本（用户的 `link_version`）
+    pub link_version: u32,
+}
+
+impl Identity {
+    /// 未启用认证时的调用方，可以访问所有频道
+    pub fn anonymous() -> Self {
+        Self {
+            name: "anonymous".to_string(),
+            credential: Credential::Anonymous,
+            entitlements: Arc::default(),
+            admin: true,
+            link_version: 0,
+        }
+    }
+
+    pub fn can_access(&self, channel: &Channel) -> bool {
+        self.entitlements.allows(channel)
+    }
+
+    /// 检查频道访问权限，无权访问时返回 403
+    pub fn check_access(&self, channel: &Channel) -> Result<()> {
+        if self.can_access(channel) {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden(format!(
+                "{} is not allowed to access channel {}",
+                self.name, channel.id
+            )))
+        }
+    }
+
+    /// 检查管理权限，不是管理员时返回 403
+    pub fn check_admin(&self) -> Result<()> {
+        if self.admin {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden(format!(
+                "{} is not allowed to use admin endpoints",
+                self.name
+            )))
+        }
+    }
+
+    /// 写入代理令牌的身份，未启用认证时为 None
+    pub fn subject(&self) -> Option<&str> {
+        match self.credential {
+            Credential::Anonymous => None,
+            _ => Some(&self.name),
+        }
+    }
+
+    /// 导出链接中附带的 `?token=`，用户名密码登录时为 None（需要另行签发链接令牌）
+    pub fn link_token(&self) -> Option<&str> {
+        match &self.credential {
+            Credential::ApiKey(token) | Credential::LinkToken(token) => Some(token),
+            _ => None,
+        }
+    }
+}
+
+/// API Key 和用户认证
+///
+/// 没有配置 API Key 和用户时不启用认证，所有请求都视为匿名调用方；
+/// 只保存 key 和密码的 SHA-256，比较摘要而不是原文
+pub struct Authenticator {
+    /// API Key 的摘要 -> 名称
+    api_keys: HashMap<[u8; 32], String>,
+    /// 用户名 -> 密码的摘要
+    users: HashMap<String, [u8; 32]>,
+    /// API Key 或用户名 -> 访问范围
+    entitlements: HashMap<String, Arc<Entitlements>>,
+    /// 管理员的 API Key 或用户名
+    admins: HashSet<String>,
+    /// 用户名 -> 导出链接令牌的版本
+    link_versions: HashMap<String, u32>,
+}
+
+impl Authenticator {
+    pub fn new(api_keys: &[ApiKeyConfig], users: &[UserConfig]) -> Self {
+        let entitlements = api_keys
+            .iter()
+            .map(|k| (&k.name, Entitlements::new(&k.groups, &k.channels)))
+            .chain(
+                users
+                    .iter()
+                    .map(|u| (&u.name, Entitlements::new(&u.groups, &u.channels))),
+            )
+            .map(|(name, entitlements)| (name.clone(), Arc::new(entitlements)))
+            .collect::<HashMap<_, _>>();
+        // 只有显式设置 `admin = true` 的条目是管理员
+        let admins = api_keys
+            .iter()
+            .filter(|k| k.admin)
+            .map(|k| &k.name)
+            .chain(users.iter().filter(|u| u.admin).map(|u| &u.name))
+            .cloned()
+            .collect();
+
+        Self {
+            api_keys: api_keys
+                .iter()
+                .map(|k| (digest(&k.key), k.name.clone()))
+                .collect(),
+            users: users
+                .iter()
+                .map(|u| (u.name.clone(), digest(&u.password)))
+                .collect(),
+            entitlements,
+            admins,
+            link_versions: users
+                .iter()
+                .map(|u| (u.name.clone(), u.link_version))
+                .collect(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.api_keys.is_empty() || !self.users.is_empty()
+    }
+
+    /// 认证请求
+    ///
+    /// 依次检查 `Authorization`（`Bearer` API Key 或 `Basic` 用户名密码）、`X-API-Key`
+    /// 请求头和 `?token=` 参数，缺少或错误的凭据返回 401
+    pub fn authenticate(&self, headers: &HeaderMap, query_token: Option<&str>) -> Result<Identity> {
+        if !self.is_enabled() {
+            return Ok(Identity::anonymous());
+        }
+
+        let authorization = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok());
+        if let Some(authorization) = authorization {
+            let (scheme, value) = authorization.split_once(' ').unwrap_or((authorization, ""));
+            if scheme.eq_ignore_ascii_case("bearer") {
+                return self.api_key(value.trim());
+            }
+            if scheme.eq_ignore_ascii_case("basic") {
+                return self.basic(value.trim());
+            }
+        }
+
+        let api_key = headers
+            .get("x-api-key")
+            .and_then(|v| v.to_str().ok())
+            .or(query_token);
+        match api_key {
+            Some(key) => self.api_key(key),
+            None => Err(AppError::Unauthorized("Missing credentials".to_string())),
+        }
+    }
+
+    /// 代理令牌中记录的身份，API Key 或用户被删除后令牌随之失效
+    pub fn token_identity(&self, subject: Option<&str>) -> Result<Identity> {
+        if !self.is_enabled() {
+            return Ok(Identity::anonymous());
+        }
+
+        let subject = subject.ok_or_else(|| {
+            AppError::Forbidden("Proxy token was issued without credentials".to_string())
+        })?;
+        if !self.entitlements.contains_key(subject) {
+            return Err(AppError::Forbidden(format!(
+                "Proxy token was issued to unknown user {}",
+                subject
+            )));
+        }
+
+        Ok(self.identity(subject, Credential::ProxyToken))
+    }
+
+    /// 导出链接令牌中记录的身份，通过链接访问时没有管理权限；用户的 `link_version` 变化后令牌失效
+    pub fn link_identity(&self, claims: &LinkClaims, token: &str) -> Result<Identity> {
+        if !self.is_enabled() {
+            return Ok(Identity::anonymous());
+        }
+        if !self.entitlements.contains_key(&claims.subject) {
+            return Err(AppError::Unauthorized(format!(
+                "Link token was issued to unknown user {}",
+                claims.subject
+            )));
+        }
+
+        let mut identity =
+            self.identity(&claims.subject, Credential::LinkToken(token.to_string()));
+        if identity.link_version != claims.version {
+            return Err(AppError::Unauthorized("Link token has been revoked".to_string()));
+        }
+        identity.admin = false;
+        Ok(identity)
+    }
+
+    fn identity(&self, name: &str, credential: Credential) -> Identity {
+        Identity {
+            name: name.to_string(),
+            credential,
+            entitlements: self.entitlements.get(name).cloned().unwrap_or_default(),
+            admin: self.admins.contains(name),
+            link_version: self.link_versions.get(name).copied().unwrap_or_default(),
+        }
+    }
+
+    fn api_key(&self, key: &str) -> Result<Identity> {
+        match self.api_keys.get(&digest(key)) {
+            Some(name) => Ok(self.identity(name, Credential::ApiKey(key.to_string()))),
+            None => Err(AppError::Unauthorized("Invalid API key".to_string())),
+        }
+    }
+
+    fn basic(&self, value: &str) -> Result<Identity> {
+        let invalid = || AppError::Unauthorized("Invalid username or password".to_string());
+
+        let decoded = STANDARD.decode(value).map_err(|_| invalid())?;
+        let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+        let (name, password) = decoded.split_once(':').ok_or_else(invalid)?;
+
+        match self.users.get(name) {
+            Some(expected) if *expected == digest(password) => {
+                Ok(self.identity(name, Credential::Password))
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+fn digest(secret: &str) -> [u8; 32] {
+    Sha256::digest(secret.as_bytes()).into()
+}
+
+/// 通配符匹配：`*` 匹配任意个字符，`?` 匹配一个字符
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // 最近一个 `*` 的位置和它当前匹配到的文本位置，失配时回溯
+    let mut star = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn authenticator() -> Authenticator {
+        Authenticator::new(
+            &[ApiKeyConfig {
+                name: "tv".to_string(),
+                key: "k-123".to_string(),
+                groups: vec!["央视*".to_string()],
+                channels: Vec::new(),
+                admin: false,
+            }],
+            &[UserConfig {
+                name: "alice".to_string(),
+                password: "secret".to_string(),
+                groups: Vec::new(),
+                channels: Vec::new(),
+                admin: false,
+                link_version: 1,
+            }],
+        )
+    }
+
+    fn headers(name: header::HeaderName, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let auth = authenticator();
+        let none = HeaderMap::new();
+
+        let identity = auth.authenticate(&none, Some("k-123")).unwrap();
+        assert_eq!(identity.name, "tv");
+        assert_eq!(identity.link_token(), Some("k-123"));
+
+        let bearer = headers(header::AUTHORIZATION, "Bearer k-123");
+        assert_eq!(auth.authenticate(&bearer, None).unwrap().name, "tv");
+        let api_key = headers(header::HeaderName::from_static("x-api-key"), "k-123");
+        assert_eq!(auth.authenticate(&api_key, None).unwrap().name, "tv");
+
+        // alice:secret
+        let basic = headers(header::AUTHORIZATION, "Basic YWxpY2U6c2VjcmV0");
+        let identity = auth.authenticate(&basic, None).unwrap();
+        assert_eq!(identity.subject(), Some("alice"));
+        assert_eq!(identity.link_token(), None);
+
+        // 缺少或错误的凭据
+        assert!(matches!(
+            auth.authenticate(&none, None),
+            Err(AppError::Unauthorized(_))
+        ));
+        assert!(auth.authenticate(&none, Some("wrong")).is_err());
+        let wrong = headers(header::AUTHORIZATION, "Basic YWxpY2U6d3Jvbmc=");
+        assert!(auth.authenticate(&wrong, Some("k-123")).is_err());
+
+        // 代理令牌中的身份
+        assert_eq!(auth.token_identity(Some("tv")).unwrap().name, "tv");
+        assert!(auth.token_identity(Some("bob")).is_err());
+        assert!(auth.token_identity(None).is_err());
+
+        // 导出链接令牌中的身份
+        let claims = |subject: &str, version| LinkClaims {
+            subject: subject.to_string(),
+            version,
+        };
+        let link = auth.link_identity(&claims("alice", 1), "link-1").unwrap();
+        assert_eq!(link.link_token(), Some("link-1"));
+        assert!(!link.admin);
+        assert!(auth.link_identity(&claims("bob", 0), "link-1").is_err());
+        // 修改 link_version 后之前签发的链接失效
+        assert!(matches!(
+            auth.link_identity(&claims("alice", 0), "link-1"),
+            Err(AppError::Unauthorized(_))
+        ));
+
+        // 未配置凭据时不启用认证
+        let open = Authenticator::new(&[], &[]);
+        assert_eq!(open.authenticate(&none, None).unwrap().subject(), None);
+        assert!(open.token_identity(None).is_ok());
+    }
+
+    #[test]
+    fn test_entitlements() {
+        let channel = |name: &str, group: &str| Channel {
+            id: format!("ch_{}", name.to_lowercase()),
+            tvg_id: name.to_string(),
+            name: name.to_string(),
+            group: group.to_string(),
+            ..Default::default()
+        };
+        let cctv1 = channel("CCTV1", "央视频道");
+        let hunan = channel("湖南卫视", "卫视频道");
+        let news = channel("BBC News", "International");
+
+        let entitlements = Entitlements::new(
+            &["央视*".to_string()],
+            &["*news".to_string(), "湖?卫视".to_string()],
+        );
+        assert!(entitlements.allows(&cctv1));
+        assert!(entitlements.allows(&hunan));
+        assert!(entitlements.allows(&news));
+        assert!(!entitlements.allows(&channel("CCTV2", "其他")));
+        assert!(Entitlements::default().allows(&cctv1));
+
+        // 身份沿用配置的访问范围，代理令牌中的身份也一样
+        let auth = authenticator();
+        let tv = auth.token_identity(Some("tv")).unwrap();
+        assert!(tv.check_access(&cctv1).is_ok());
+        assert!(matches!(
+            tv.check_access(&hunan),
+            Err(AppError::Forbidden(_))
+        ));
+        let alice = auth.token_identity(Some("alice")).unwrap();
+        assert!(alice.can_access(&hunan));
+
+        // 不限制访问范围的条目也要显式设置 admin 才是管理员
+        assert!(matches!(alice.check_admin(), Err(AppError::Forbidden(_))));
+        assert!(matches!(tv.check_admin(), Err(AppError::Forbidden(_))));
+        assert!(Identity::anonymous().check_admin().is_ok());
+
+        assert!(wildcard_match("a*b?d", "axxbcd"));
+        assert!(wildcard_match("*", ""));
+        assert!(!wildcard_match("a*b", "acbx"));
+    }
+}
@@ -0,0 +1,232 @@
+use chrono::DateTime;
+
+use crate::error::{AppError, Result};
+use crate::models::{CatchupMode, Channel};
+
+/// 回看地址生成器
+///
+/// 根据频道的 catchup 配置和请求的时间段展开 catchup-source 模板
+pub struct CatchupResolver;
+
+impl CatchupResolver {
+    /// 回看时长上限（秒）
+    pub const MAX_DURATION: i64 = 7 * 86400;
+
+    /// 检查回看开始时间：必须是过去的 Unix 时间戳
+    pub fn check_start(start: i64, now: i64) -> Result<()> {
+        if start < 0 {
+            return Err(AppError::BadRequest(format!(
+                "start ({}) must be a Unix timestamp",
+                start
+            )));
+        }
+        if start >= now {
+            return Err(AppError::BadRequest(format!(
+                "start ({}) must be in the past",
+                start
+            )));
+        }
+        Ok(())
+    }
+
+    /// 生成回看地址
+    ///
+    /// `start` 为节目开始时间（Unix 时间戳，秒），`duration` 为时长（秒），`now` 为当前时间
+    pub fn resolve(channel: &Channel, start: i64, duration: i64, now: i64) -> Result<String> {
+        let catchup = channel.catchup.as_ref().ok_or_else(|| {
+            AppError::BadRequest(format!("Channel {} does not support catch-up", channel.id))
+        })?;
+
+        if duration <= 0 || duration > Self::MAX_DURATION {
+            return Err(AppError::BadRequest(format!(
+                "duration must be between 1 and {}, got {}",
+                Self::MAX_DURATION,
+                duration
+            )));
+        }
+        Self::check_start(start, now)?;
+        if let Some(days) = catchup.days
+            && start < now - i64::from(days) * 86400
+        {
+            return Err(AppError::BadRequest(format!(
+                "start ({}) is outside the {}-day catch-up window of channel {}",
+                start, days, channel.id
+            )));
+        }
+
+        let template = catchup.source.as_deref().unwrap_or_default();
+        let url = match catchup.mode {
+            CatchupMode::Default => expand(template, start, duration, now),
+            CatchupMode::Append => {
+                append_query(&channel.url, &expand(template, start, duration, now))
+            }
+            CatchupMode::Shift => append_query(
+                &channel.url,
+                &expand("?utc={utc}&lutc={lutc}", start, duration, now),
+            ),
+        };
+
+        Ok(url)
+    }
+}
+
+/// 把模板追加到直播地址之后，直播地址已有查询参数时 `?` 改为 `&`
+fn append_query(url: &str, suffix: &str) -> String {
+    match suffix.strip_prefix('?') {
+        Some(query) if url.contains('?') => format!("{}&{}", url, query),
+        _ => format!("{}{}", url, suffix),
+    }
+}
+
+/// 展开回看模板中的占位符
+///
+/// 支持 `{utc}`、`{start}`、`{utcend}`、`{end}`、`{lutc}`、`{now}`、`{timestamp}`、
+/// `{duration}`、`{offset}`（以及 `${...}` 写法）、`{duration:60}` 形式的除数、
+/// `{utc:YmdHMS}` 形式的时间格式和 `{Y}`、`{m}`、`{d}`、`{H}`、`{M}`、`{S}`；
+/// 无法识别的占位符原样保留
+fn expand(template: &str, start: i64, duration: i64, now: i64) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(open) = rest.find('{') {
+        let Some(close) = rest[open..].find('}').map(|i| open + i) else {
+            break;
+        };
+
+        let dollar = rest[..open].ends_with('$');
+        let prefix = if dollar {
+            &rest[..open - 1]
+        } else {
+            &rest[..open]
+        };
+        output.push_str(prefix);
+
+        let placeholder = &rest[open + 1..close];
+        match placeholder_value(placeholder, start, duration, now) {
+            Some(value) => output.push_str(&value),
+            None => output.push_str(&rest[open - usize::from(dollar)..=close]),
+        }
+
+        rest = &rest[close + 1..];
+    }
+
+    output.push_str(rest);
+    output
+}
+
+fn placeholder_value(placeholder: &str, start: i64, duration: i64, now: i64) -> Option<String> {
+    let end = start + duration;
+    let (name, arg) = match placeholder.split_once(':') {
+        Some((name, arg)) => (name, Some(arg)),
+        None => (placeholder, None),
+    };
+
+    let timestamp = match name {
+        "utc" | "start" | "timestamp" => Some(start),
+        "utcend" | "end" => Some(end),
+        "lutc" | "now" => Some(now),
+        _ => None,
+    };
+    if let Some(timestamp) = timestamp {
+        return match arg {
+            Some(format) => format_time(timestamp, format),
+            None => Some(timestamp.to_string()),
+        };
+    }
+
+    let seconds = match name {
+        "duration" => duration,
+        "offset" => now - start,
+        "Y" | "m" | "d" | "H" | "M" | "S" if arg.is_none() => {
+            return format_time(start, name);
+        }
+        _ => return None,
+    };
+
+    match arg {
+        Some(divider) => {
+            let divider: i64 = divider.parse().ok().filter(|d| *d > 0)?;
+            Some((seconds / divider).to_string())
+        }
+        None => Some(seconds.to_string()),
+    }
+}
+
+/// 按 `YmdHMS` 形式的格式输出 UTC 时间，其他字符原样保留
+fn format_time(timestamp: i64, format: &str) -> Option<String> {
+    let time = DateTime::from_timestamp(timestamp, 0)?;
+    let pattern: String = format
+        .chars()
+        .map(|c| match c {
+            'Y' | 'm' | 'd' | 'H' | 'M' | 'S' => format!("%{}", c),
+            '%' => "%%".to_string(),
+            c => c.to_string(),
+        })
+        .collect();
+
+    Some(time.format(&pattern).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Catchup;
+
+    fn channel(mode: CatchupMode, source: Option<&str>) -> Channel {
+        Channel {
+            id: "ch_1".to_string(),
+            url: "http://example.com/live/index.m3u8?token=abc".to_string(),
+            catchup: Some(Catchup {
+                mode,
+                days: Some(7),
+                source: source.map(str::to_string),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_catchup_templates() {
+        let start = 1736942400; // 2025-01-15 12:00:00 UTC
+        let now = start + 7200;
+
+        let default = channel(
+            CatchupMode::Default,
+            Some(
+                "http://example.com/tv/{utc:Y-m-d-H-M-S}/{duration:60}.m3u8?s=${start}&o=${offset}&x={unknown}",
+            ),
+        );
+        assert_eq!(
+            CatchupResolver::resolve(&default, start, 1800, now).unwrap(),
+            "http://example.com/tv/2025-01-15-12-00-00/30.m3u8?s=1736942400&o=7200&x={unknown}"
+        );
+
+        let append = channel(
+            CatchupMode::Append,
+            Some("?playseek={Y}{m}{d}{H}{M}{S}-{utcend}"),
+        );
+        assert_eq!(
+            CatchupResolver::resolve(&append, start, 1800, now).unwrap(),
+            "http://example.com/live/index.m3u8?token=abc&playseek=20250115120000-1736944200"
+        );
+
+        let shift = channel(CatchupMode::Shift, None);
+        assert_eq!(
+            CatchupResolver::resolve(&shift, start, 1800, now).unwrap(),
+            "http://example.com/live/index.m3u8?token=abc&utc=1736942400&lutc=1736949600"
+        );
+    }
+
+    #[test]
+    fn test_catchup_rejects_invalid_requests() {
+        let channel = channel(CatchupMode::Shift, None);
+        let now = 1736942400;
+
+        assert!(CatchupResolver::resolve(&channel, now + 60, 1800, now).is_err());
+        assert!(CatchupResolver::resolve(&channel, now - 60, 0, now).is_err());
+        assert!(CatchupResolver::resolve(&channel, now - 60, i64::MAX, now).is_err());
+        assert!(CatchupResolver::resolve(&channel, i64::MIN, 1800, now).is_err());
+        assert!(CatchupResolver::resolve(&channel, now - 8 * 86400, 1800, now).is_err());
+        assert!(CatchupResolver::resolve(&Channel::default(), now - 60, 1800, now).is_err());
+    }
+}
@@ -1,52 +1,438 @@
 use crate::error::{AppError, Result};
-use crate::models::Channel;
-use crate::services::M3uParser;
-use parking_lot::RwLock;
+use crate::models::{
+    Channel, ChannelHealth, HealthStatus, ParseReport, PlaylistInfo, SourceHeader, SourceStatus,
+};
+use parking_lot::{RwLock, RwLockWriteGuard};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::Url;
+
+/// 当前 Unix 时间戳（秒）
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// 单个来源的频道及加载状态
+struct SourceEntry {
+    status: SourceStatus,
+    channels: Vec<Channel>,
+    header: BTreeMap<String, String>,
+    report: Option<ParseReport>,
+}
+
+/// 合并后的频道目录，频道和重复频道映射在同一把锁下一起替换
+#[derive(Default)]
+struct Catalog {
+    channels: Vec<Channel>,
+    /// 被合并的重复频道 ID -> 主频道 ID
+    duplicates: HashMap<String, String>,
+}
 
 #[derive(Clone)]
 pub struct ChannelManager {
-    channels: Arc<RwLock<Vec<Channel>>>,
+    /// 合并后的频道目录
+    catalog: Arc<RwLock<Catalog>>,
+    /// 按注册顺序排列的来源
+    sources: Arc<RwLock<Vec<SourceEntry>>>,
+    /// 旧 ID -> 当前 ID，保证重新加载后旧链接仍然有效（只保留指向现有频道的别名）
+    aliases: Arc<RwLock<HashMap<String, String>>>,
+    /// 上游 URL -> 最近一次健康检查结果（重新加载后仍然保留）
+    health: Arc<RwLock<HashMap<String, ChannelHealth>>>,
+    /// 频道地址（含备用地址和回看地址）的主机名 -> 端口，用于代理的主机白名单
+    origins: Arc<RwLock<HashMap<String, HashSet<u16>>>>,
+    /// 是否把重复频道合并为带备用地址的同一频道
+    failover: bool,
 }
 
 impl ChannelManager {
     /// 创建新的频道管理器
     pub fn new() -> Self {
         Self {
-            channels: Arc::new(RwLock::new(Vec::new())),
+            catalog: Arc::new(RwLock::new(Catalog::default())),
+            sources: Arc::new(RwLock::new(Vec::new())),
+            aliases: Arc::new(RwLock::new(HashMap::new())),
+            health: Arc::new(RwLock::new(HashMap::new())),
+            origins: Arc::new(RwLock::new(HashMap::new())),
+            failover: false,
+        }
+    }
+
+    /// 启用或关闭重复频道合并（故障转移）
+    pub fn with_failover(mut self, enabled: bool) -> Self {
+        self.failover = enabled;
+        self
+    }
+
+    /// 注册来源（合并顺序与注册顺序一致）
+    pub fn register_source(&self, name: &str, location: &str) {
+        let mut sources = self.sources.write();
+        if sources.iter().any(|s| s.status.name == name) {
+            return;
         }
+
+        sources.push(SourceEntry {
+            status: SourceStatus {
+                name: name.to_string(),
+                location: location.to_string(),
+                channel_count: 0,
+                last_loaded_at: None,
+                last_error: None,
+            },
+            channels: Vec::new(),
+            header: BTreeMap::new(),
+            report: None,
+        });
     }
 
-    /// 从 M3U 文件加载频道
-    pub fn load_from_file(&self, path: &str) -> Result<usize> {
-        let parsed_channels = M3uParser::parse_file(path)?;
-        let count = parsed_channels.len();
+    /// 替换某个来源的频道并重建合并目录
+    pub fn update_source(&self, name: &str, source_channels: Vec<Channel>) -> Result<usize> {
+        let mut sources = self.sources.write();
+        let entry = sources
+            .iter_mut()
+            .find(|s| s.status.name == name)
+            .ok_or_else(|| AppError::Internal(format!("Unknown source: {}", name)))?;
+
+        let renamed = Self::match_renamed(&entry.channels, &source_channels);
+
+        let count = source_channels.len();
+        entry.channels = source_channels;
+        entry.status.channel_count = count;
+        entry.status.last_loaded_at = Some(unix_now());
+        entry.status.last_error = None;
+
+        let live_ids: HashSet<&str> = sources
+            .iter()
+            .flat_map(|s| s.channels.iter().map(|c| c.id.as_str()))
+            .collect();
+        self.update_aliases(renamed, &live_ids);
 
-        let mut channels = self.channels.write();
-        *channels = parsed_channels;
+        let (mut merged, duplicates) = if self.failover {
+            Self::merge_duplicates(sources.iter().flat_map(|s| s.channels.iter().cloned()))
+        } else {
+            let channels = sources.iter().flat_map(|s| s.channels.iter().cloned());
+            (channels.collect(), HashMap::new())
+        };
 
-        tracing::info!("Loaded {} channels from {}", count, path);
+        let health = self.health.read();
+        for channel in &mut merged {
+            Self::apply_health(channel, &health);
+        }
+        *self.origins.write() = Self::collect_origins(&merged);
+        *self.catalog.write() = Catalog {
+            channels: merged,
+            duplicates,
+        };
+
+        tracing::info!("Loaded {} channels from source {}", count, name);
         Ok(count)
     }
 
+    /// 把 tvg-id 或名称相同的频道合并为一个频道，后出现的地址作为备用地址
+    ///
+    /// 两个频道都有 tvg-id 但不相同时不按名称合并；流类型不同的频道不合并。
+    /// 返回合并后的频道和被合并的频道 ID -> 主频道 ID
+    fn merge_duplicates(
+        channels: impl Iterator<Item = Channel>,
+    ) -> (Vec<Channel>, HashMap<String, String>) {
+        let mut merged: Vec<Channel> = Vec::new();
+        let mut by_tvg_id: HashMap<String, usize> = HashMap::new();
+        let mut by_name: HashMap<String, usize> = HashMap::new();
+        let mut duplicates = HashMap::new();
+
+        for channel in channels {
+            let tvg_id = channel.tvg_id.trim().to_lowercase();
+            let name = channel.name.trim().to_lowercase();
+
+            let primary = by_tvg_id
+                .get(&tvg_id)
+                .filter(|_| !tvg_id.is_empty())
+                .or_else(|| {
+                    by_name.get(&name).filter(|&&i| {
+                        tvg_id.is_empty() || merged[i].tvg_id.trim().is_empty()
+                    })
+                })
+                .copied()
+                .filter(|&i| merged[i].stream_type == channel.stream_type);
+
+            match primary {
+                Some(i) => {
+                    let primary = &mut merged[i];
+                    if !primary.stream_urls().any(|url| url == channel.url) {
+                        primary.backup_urls.push(channel.url.clone());
+                    }
+                    duplicates.insert(channel.id, primary.id.clone());
+                }
+                None => {
+                    if !tvg_id.is_empty() {
+                        by_tvg_id.entry(tvg_id).or_insert(merged.len());
+                    }
+                    by_name.entry(name).or_insert(merged.len());
+                    merged.push(channel);
+                }
+            }
+        }
+
+        (merged, duplicates)
+    }
+
+    /// 计算频道的健康状态：主地址在线时使用主地址的结果，否则使用第一个在线的备用地址
+    fn apply_health(channel: &mut Channel, health: &HashMap<String, ChannelHealth>) {
+        let primary = health.get(&channel.url);
+        let online = channel
+            .stream_urls()
+            .filter_map(|url| health.get(url))
+            .find(|h| h.status == HealthStatus::Online);
+
+        channel.health = online.or(primary).cloned();
+    }
+
+    /// 收集频道地址的主机名和端口（回看模板能解析为 URL 时也包含在内）
+    fn collect_origins(channels: &[Channel]) -> HashMap<String, HashSet<u16>> {
+        let mut origins: HashMap<String, HashSet<u16>> = HashMap::new();
+        let urls = channels.iter().flat_map(|c| {
+            c.stream_urls()
+                .chain(c.catchup.as_ref().and_then(|catchup| catchup.source.as_deref()))
+        });
+
+        for url in urls {
+            if let Ok(url) = Url::parse(url)
+                && let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default())
+            {
+                origins.entry(host.to_string()).or_default().insert(port);
+            }
+        }
+        origins
+    }
+
+    /// 主机（和端口）是否属于某个频道的地址，`port` 为 None 时只比较主机名
+    pub fn is_channel_origin(&self, host: &str, port: Option<u16>) -> bool {
+        self.origins
+            .read()
+            .get(host)
+            .is_some_and(|ports| port.is_none_or(|port| ports.contains(&port)))
+    }
+
+    /// 选择播放地址：按优先级返回第一个未被判定为离线的地址，全部离线时返回主地址
+    pub fn select_stream_url(&self, channel: &Channel) -> String {
+        let health = self.health.read();
+        channel
+            .stream_urls()
+            .find(|url| health.get(*url).map(|h| h.status) != Some(HealthStatus::Offline))
+            .unwrap_or(&channel.url)
+            .to_string()
+    }
+
+    /// 某个上游地址是否已被健康检查判定为离线
+    pub fn is_offline(&self, url: &str) -> bool {
+        self.health.read().get(url).map(|h| h.status) == Some(HealthStatus::Offline)
+    }
+
+    /// 找出重新加载后 ID 发生变化的频道（按 URL 或 tvg-id + 名称匹配）
+    fn match_renamed(old: &[Channel], new: &[Channel]) -> HashMap<String, String> {
+        let new_ids: HashSet<&str> = new.iter().map(|c| c.id.as_str()).collect();
+        let mut renamed = HashMap::new();
+
+        for old_channel in old.iter().filter(|c| !new_ids.contains(c.id.as_str())) {
+            let matched = new
+                .iter()
+                .find(|c| c.url == old_channel.url)
+                .or_else(|| {
+                    new.iter().find(|c| {
+                        !old_channel.tvg_id.is_empty()
+                            && c.tvg_id == old_channel.tvg_id
+                            && c.name == old_channel.name
+                    })
+                });
+
+            if let Some(new_channel) = matched {
+                renamed.insert(old_channel.id.clone(), new_channel.id.clone());
+            }
+        }
+
+        renamed
+    }
+
+    /// 合并新的别名，把指向已失效 ID 的旧别名重新指向当前 ID，
+    /// 并删除指向已不存在的频道的别名（`live_ids` 为所有来源当前的频道 ID）
+    fn update_aliases(&self, renamed: HashMap<String, String>, live_ids: &HashSet<&str>) {
+        let mut aliases = self.aliases.write();
+
+        for target in aliases.values_mut() {
+            if let Some(current) = renamed.get(target.as_str()) {
+                *target = current.clone();
+            }
+        }
+        aliases.extend(renamed);
+        // 当前 ID 不再作为别名，只保留指向现有频道的别名
+        aliases.retain(|alias, target| {
+            !live_ids.contains(alias.as_str()) && live_ids.contains(target.as_str())
+        });
+    }
+
+    /// 旧 ID 别名指向的当前 ID
+    fn resolve_alias(&self, id: &str) -> String {
+        self.aliases
+            .read()
+            .get(id)
+            .cloned()
+            .unwrap_or_else(|| id.to_string())
+    }
+
+    /// 被合并的重复频道 ID 指向的主频道 ID
+    fn resolve_duplicate(catalog: &Catalog, id: String) -> String {
+        catalog.duplicates.get(&id).cloned().unwrap_or(id)
+    }
+
+    /// 记录来源加载失败（保留该来源原有频道）
+    pub fn record_source_error(&self, name: &str, error: &str) {
+        let mut sources = self.sources.write();
+        if let Some(entry) = sources.iter_mut().find(|s| s.status.name == name) {
+            entry.status.last_error = Some(error.to_string());
+        }
+    }
+
+    /// 保存来源的 `#EXTM3U` 头部属性
+    pub fn set_source_header(&self, name: &str, header: BTreeMap<String, String>) {
+        let mut sources = self.sources.write();
+        if let Some(entry) = sources.iter_mut().find(|s| s.status.name == name) {
+            entry.header = header;
+        }
+    }
+
+    /// 合并所有来源的头部属性
+    ///
+    /// EPG 地址（x-tvg-url / url-tvg）去重后用逗号拼接，其他属性以先注册的来源为准
+    pub fn get_playlist_header(&self) -> BTreeMap<String, String> {
+        let sources = self.sources.read();
+        Self::merge_header(&sources.iter().collect::<Vec<_>>())
+    }
+
+    fn merge_header(sources: &[&SourceEntry]) -> BTreeMap<String, String> {
+        let mut header = BTreeMap::new();
+
+        for entry in sources {
+            for (key, value) in &entry.header {
+                if key != "x-tvg-url" && key != "url-tvg" {
+                    header.entry(key.clone()).or_insert_with(|| value.clone());
+                }
+            }
+        }
+
+        let epg_urls = Self::collect_epg_urls(sources);
+        if !epg_urls.is_empty() {
+            header.insert("x-tvg-url".to_string(), epg_urls.join(","));
+        }
+
+        header
+    }
+
+    /// 获取播放列表元数据（EPG 地址、合并后的头部属性和各来源的原始头部）
+    pub fn get_playlist_info(&self) -> PlaylistInfo {
+        let sources = self.sources.read();
+        Self::playlist_info(&sources.iter().collect::<Vec<_>>())
+    }
+
+    /// 获取播放列表元数据，只包含有可访问频道（`allows`）的来源
+    pub fn get_playlist_info_for(&self, allows: impl Fn(&Channel) -> bool) -> PlaylistInfo {
+        let sources = self.sources.read();
+        let visible: Vec<&SourceEntry> = sources
+            .iter()
+            .filter(|s| s.channels.iter().any(&allows))
+            .collect();
+        Self::playlist_info(&visible)
+    }
+
+    fn playlist_info(sources: &[&SourceEntry]) -> PlaylistInfo {
+        PlaylistInfo {
+            epg_urls: Self::collect_epg_urls(sources),
+            attributes: Self::merge_header(sources),
+            sources: sources
+                .iter()
+                .map(|s| SourceHeader {
+                    name: s.status.name.clone(),
+                    attributes: s.header.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    /// 收集所有来源声明的 EPG 地址（保持顺序并去重）
+    fn collect_epg_urls(sources: &[&SourceEntry]) -> Vec<String> {
+        let mut epg_urls: Vec<String> = Vec::new();
+
+        for entry in sources {
+            let declared = ["x-tvg-url", "url-tvg"]
+                .iter()
+                .filter_map(|key| entry.header.get(*key));
+            for value in declared {
+                for url in value.split(',').map(str::trim).filter(|u| !u.is_empty()) {
+                    if !epg_urls.iter().any(|u| u == url) {
+                        epg_urls.push(url.to_string());
+                    }
+                }
+            }
+        }
+
+        epg_urls
+    }
+
+    /// 保存来源最近一次的解析报告（解析失败时也会保存）
+    pub fn record_parse_report(&self, report: ParseReport) {
+        let mut sources = self.sources.write();
+        if let Some(entry) = sources.iter_mut().find(|s| s.status.name == report.source) {
+            entry.report = Some(report);
+        }
+    }
+
+    /// 获取所有来源最近一次的解析报告
+    pub fn get_parse_reports(&self) -> Vec<ParseReport> {
+        self.sources
+            .read()
+            .iter()
+            .filter_map(|s| s.report.clone())
+            .collect()
+    }
+
+    /// 获取某个来源当前的频道数
+    pub fn get_source_channel_count(&self, name: &str) -> usize {
+        self.sources
+            .read()
+            .iter()
+            .find(|s| s.status.name == name)
+            .map(|s| s.channels.len())
+            .unwrap_or(0)
+    }
+
+    /// 获取所有来源的状态
+    pub fn get_source_statuses(&self) -> Vec<SourceStatus> {
+        self.sources.read().iter().map(|s| s.status.clone()).collect()
+    }
+
     /// 获取所有频道
     pub fn get_all_channels(&self) -> Vec<Channel> {
-        self.channels.read().clone()
+        self.catalog.read().channels.clone()
     }
 
-    /// 根据 ID 获取频道
+    /// 根据 ID 获取频道（旧 ID 通过别名表解析，被合并的重复频道返回主频道）
     pub fn get_channel_by_id(&self, id: &str) -> Result<Channel> {
-        let channels = self.channels.read();
-        channels
+        let alias = self.resolve_alias(id);
+        let catalog = self.catalog.read();
+        let resolved = Self::resolve_duplicate(&catalog, alias);
+        catalog
+            .channels
             .iter()
-            .find(|c| c.id == id)
+            .find(|c| c.id == resolved)
             .cloned()
             .ok_or_else(|| AppError::ChannelNotFound(id.to_string()))
     }
 
     /// 根据分组筛选频道
     pub fn get_channels_by_group(&self, group: &str) -> Vec<Channel> {
-        let channels = self.channels.read();
+        let channels = &self.catalog.read().channels;
         channels
             .iter()
             .filter(|c| c.group == group)
@@ -56,7 +442,7 @@ impl ChannelManager {
 
     /// 搜索频道（按名称）
     pub fn search_channels(&self, query: &str) -> Vec<Channel> {
-        let channels = self.channels.read();
+        let channels = &self.catalog.read().channels;
         let query_lower = query.to_lowercase();
 
         channels
@@ -71,7 +457,7 @@ impl ChannelManager {
 
     /// 获取所有分组
     pub fn get_all_groups(&self) -> Vec<String> {
-        let channels = self.channels.read();
+        let channels = &self.catalog.read().channels;
         let mut groups: Vec<String> = channels
             .iter()
             .map(|c| c.group.clone())
@@ -82,14 +468,66 @@ impl ChannelManager {
         groups
     }
 
-    /// 获取频道总数
-    pub fn get_channel_count(&self) -> usize {
-        self.channels.read().len()
+    /// 每个上游 URL（含备用地址）取第一个使用它的频道（用于健康检查）
+    ///
+    /// 返回的频道的 `url` 为待检查的地址
+    pub fn get_unique_url_channels(&self) -> Vec<Channel> {
+        let channels = &self.catalog.read().channels;
+        let mut seen = HashSet::new();
+        let mut result = Vec::new();
+
+        for channel in channels.iter() {
+            for url in channel.stream_urls() {
+                if seen.insert(url.to_string()) {
+                    result.push(Channel {
+                        url: url.to_string(),
+                        backup_urls: Vec::new(),
+                        ..channel.clone()
+                    });
+                }
+            }
+        }
+
+        result
+    }
+
+    /// 记录一批上游 URL 的健康检查结果，并更新使用这些 URL 的频道
+    ///
+    /// 一轮检查的结果一次写入，频道目录只加一次写锁、遍历一次
+    pub fn record_health(&self, results: impl IntoIterator<Item = (String, ChannelHealth)>) {
+        let mut health = self.health.write();
+        let mut checked = HashSet::new();
+        for (url, result) in results {
+            health.insert(url.clone(), result);
+            checked.insert(url);
+        }
+        let health = RwLockWriteGuard::downgrade(health);
+
+        let mut catalog = self.catalog.write();
+        for channel in catalog
+            .channels
+            .iter_mut()
+            .filter(|c| c.stream_urls().any(|u| checked.contains(u)))
+        {
+            Self::apply_health(channel, &health);
+        }
     }
 
-    /// 重新加载频道列表
-    pub fn reload(&self, path: &str) -> Result<usize> {
-        self.load_from_file(path)
+    /// 清理已不在频道目录中的 URL 的检查结果
+    pub fn prune_health(&self) {
+        let urls: HashSet<String> = self
+            .catalog
+            .read()
+            .channels
+            .iter()
+            .flat_map(|c| c.stream_urls().map(str::to_string))
+            .collect();
+        self.health.write().retain(|url, _| urls.contains(url));
+    }
+
+    /// 获取频道总数
+    pub fn get_channel_count(&self) -> usize {
+        self.catalog.read().channels.len()
     }
 }
 
@@ -113,27 +551,182 @@ mod tests {
             Channel {
                 id: "test1".to_string(),
                 tvg_id: "test1".to_string(),
+                tvg_name: None,
                 name: "测试频道1".to_string(),
                 logo: None,
                 group: "测试组".to_string(),
                 url: "http://example.com/test1.m3u8".to_string(),
                 stream_type: StreamType::HLS,
+                source: "default".to_string(),
+                ..Default::default()
             },
             Channel {
                 id: "test2".to_string(),
                 tvg_id: "test2".to_string(),
+                tvg_name: None,
                 name: "测试频道2".to_string(),
                 logo: None,
                 group: "测试组".to_string(),
                 url: "http://example.com/test2.m3u8".to_string(),
                 stream_type: StreamType::HLS,
+                source: "default".to_string(),
+                ..Default::default()
             },
         ];
 
-        *manager.channels.write() = test_channels;
+        manager.catalog.write().channels = test_channels;
 
         assert_eq!(manager.get_channel_count(), 2);
         assert!(manager.get_channel_by_id("test1").is_ok());
         assert_eq!(manager.get_channels_by_group("测试组").len(), 2);
     }
+
+    #[test]
+    fn test_merge_sources() {
+        let manager = ChannelManager::new();
+        manager.register_source("a", "./a.m3u");
+        manager.register_source("b", "http://example.com/b.m3u");
+
+        let channel = |id: &str, source: &str| Channel {
+            id: id.to_string(),
+            tvg_id: id.to_string(),
+            tvg_name: None,
+            name: id.to_string(),
+            logo: None,
+            group: "测试组".to_string(),
+            url: format!("http://example.com/{}.m3u8", id),
+            stream_type: StreamType::HLS,
+            source: source.to_string(),
+            ..Default::default()
+        };
+
+        manager.update_source("b", vec![channel("b1", "b")]).unwrap();
+        manager
+            .update_source("a", vec![channel("a1", "a"), channel("a2", "a")])
+            .unwrap();
+        assert_eq!(manager.get_channel_count(), 3);
+        assert_eq!(manager.get_all_channels()[0].id, "a1");
+
+        manager.record_source_error("b", "timeout");
+        manager.update_source("a", vec![channel("a1", "a")]).unwrap();
+        assert_eq!(manager.get_channel_count(), 2);
+
+        let statuses = manager.get_source_statuses();
+        assert_eq!(statuses[0].channel_count, 1);
+        assert!(statuses[0].last_loaded_at.is_some());
+        assert_eq!(statuses[1].last_error.as_deref(), Some("timeout"));
+        assert!(manager.update_source("c", Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_alias_after_reload() {
+        let manager = ChannelManager::new();
+        manager.register_source("a", "./a.m3u");
+
+        let channel = |id: &str, url: &str| Channel {
+            id: id.to_string(),
+            tvg_id: "CCTV1".to_string(),
+            tvg_name: None,
+            name: "CCTV-1".to_string(),
+            logo: None,
+            group: "央视".to_string(),
+            url: url.to_string(),
+            stream_type: StreamType::HLS,
+            source: "a".to_string(),
+            ..Default::default()
+        };
+
+        manager
+            .update_source("a", vec![channel("old", "http://example.com/1.m3u8")])
+            .unwrap();
+        manager
+            .update_source("a", vec![channel("mid", "http://example.com/2.m3u8")])
+            .unwrap();
+        manager
+            .update_source("a", vec![channel("new", "http://example.com/2.m3u8")])
+            .unwrap();
+
+        assert_eq!(manager.get_channel_by_id("old").unwrap().id, "new");
+        assert_eq!(manager.get_channel_by_id("mid").unwrap().id, "new");
+        assert_eq!(manager.get_channel_by_id("new").unwrap().id, "new");
+
+        // 频道被删除后，指向它的别名也被清理
+        let other = Channel {
+            tvg_id: "CCTV2".to_string(),
+            ..channel("other", "http://example.com/3.m3u8")
+        };
+        manager.update_source("a", vec![other]).unwrap();
+        assert!(manager.get_channel_by_id("old").is_err());
+        assert!(manager.aliases.read().is_empty());
+    }
+
+    #[test]
+    fn test_failover_grouping() {
+        let manager = ChannelManager::new().with_failover(true);
+        manager.register_source("a", "./a.m3u");
+        manager.register_source("b", "./b.m3u");
+
+        let channel = |id: &str, tvg_id: &str, name: &str, url: &str| Channel {
+            id: id.to_string(),
+            tvg_id: tvg_id.to_string(),
+            name: name.to_string(),
+            url: url.to_string(),
+            stream_type: StreamType::HLS,
+            ..Default::default()
+        };
+
+        manager
+            .update_source(
+                "a",
+                vec![
+                    channel("a1", "CCTV1", "CCTV-1", "http://telecom/1.m3u8"),
+                    channel("a2", "", "湖南卫视", "http://telecom/hn.m3u8"),
+                    channel("a3", "CCTV2", "新闻", "http://telecom/2.m3u8"),
+                ],
+            )
+            .unwrap();
+        manager
+            .update_source(
+                "b",
+                vec![
+                    channel("b1", "cctv1", "CCTV1 HD", "http://unicom/1.m3u8"),
+                    channel("b2", "HUNAN", "湖南卫视", "http://unicom/hn.m3u8"),
+                    channel("b3", "CCTV13", "新闻", "http://unicom/13.m3u8"),
+                ],
+            )
+            .unwrap();
+
+        let channels = manager.get_all_channels();
+        assert_eq!(channels.len(), 4);
+        assert_eq!(channels[0].backup_urls, vec!["http://unicom/1.m3u8"]);
+        assert_eq!(channels[1].backup_urls, vec!["http://unicom/hn.m3u8"]);
+        assert!(channels[2].backup_urls.is_empty());
+        assert_eq!(channels[3].id, "b3");
+
+        assert_eq!(manager.get_channel_by_id("b1").unwrap().id, "a1");
+        assert_eq!(manager.get_unique_url_channels().len(), 6);
+
+        let offline = ChannelHealth {
+            status: HealthStatus::Offline,
+            latency_ms: None,
+            last_checked: 0,
+            error: None,
+        };
+        let online = ChannelHealth {
+            status: HealthStatus::Online,
+            latency_ms: Some(10),
+            ..offline.clone()
+        };
+
+        let cctv1 = manager.get_channel_by_id("a1").unwrap();
+        assert_eq!(manager.select_stream_url(&cctv1), "http://telecom/1.m3u8");
+
+        manager.record_health([
+            ("http://telecom/1.m3u8".to_string(), offline),
+            ("http://unicom/1.m3u8".to_string(), online),
+        ]);
+        let cctv1 = manager.get_channel_by_id("a1").unwrap();
+        assert_eq!(manager.select_stream_url(&cctv1), "http://unicom/1.m3u8");
+        assert_eq!(cctv1.health_status(), HealthStatus::Online);
+    }
 }
@@ -0,0 +1,414 @@
+use axum::body::Bytes;
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, info, warn};
+
+use crate::error::Result;
+use crate::services::channel_manager::unix_now;
+use crate::services::proxy_cache::CachedSegment;
+
+/// 磁盘缓存中的一个文件
+struct DiskEntry {
+    size: u64,
+    /// 最近访问序号，越小越久未使用
+    tick: u64,
+}
+
+/// 磁盘缓存索引：记录每个文件的大小和访问顺序
+#[derive(Default)]
+struct DiskIndex {
+    entries: HashMap<String, DiskEntry>,
+    lru: BTreeMap<u64, String>,
+    next_tick: u64,
+    total: u64,
+}
+
+impl DiskIndex {
+    /// 记录文件（已存在时更新大小）并标记为最近使用
+    fn insert(&mut self, key: String, size: u64) {
+        self.remove(&key);
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        self.lru.insert(tick, key.clone());
+        self.entries.insert(key, DiskEntry { size, tick });
+        self.total += size;
+    }
+
+    fn touch(&mut self, key: &str) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.lru.remove(&entry.tick);
+            entry.tick = self.next_tick;
+            self.next_tick += 1;
+            self.lru.insert(entry.tick, key.to_string());
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> bool {
+        match self.entries.remove(key) {
+            Some(entry) => {
+                self.lru.remove(&entry.tick);
+                self.total -= entry.size;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 淘汰最久未使用的文件直到总大小不超过 `capacity`，返回被淘汰的键
+    fn evict(&mut self, capacity: u64) -> Vec<String> {
+        let mut evicted = Vec::new();
+        while self.total > capacity {
+            let Some((_, key)) = self.lru.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&key) {
+                self.total -= entry.size;
+            }
+            evicted.push(key);
+        }
+        evicted
+    }
+}
+
+/// 视频片段磁盘缓存
+///
+/// 每个片段保存为一个以 URL 的 SHA-256 命名的文件，文件首行记录写入时间和 Content-Type；
+/// 启动时扫描目录重建索引（按修改时间确定使用顺序），超出容量时按最近最少使用淘汰
+pub struct DiskCache {
+    dir: PathBuf,
+    capacity: u64,
+    ttl: Duration,
+    index: Mutex<DiskIndex>,
+    next_tmp: AtomicU64,
+}
+
+impl DiskCache {
+    /// 打开缓存目录，`capacity` 为总字节数，超过 `ttl` 的文件视为过期
+    pub fn open(dir: impl Into<PathBuf>, capacity: u64, ttl: Duration) -> Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+
+            // 上次退出时未写完的文件
+            if is_tmp(name) {
+                let _ = std::fs::remove_file(&path);
+                continue;
+            }
+            if !is_key(name) {
+                continue;
+            }
+
+            let metadata = entry.metadata()?;
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            files.push((modified, name.to_string(), metadata.len()));
+        }
+        files.sort();
+
+        let mut index = DiskIndex::default();
+        for (_, key, size) in files {
+            index.insert(key, size);
+        }
+        let evicted = index.evict(capacity);
+        for key in &evicted {
+            let _ = std::fs::remove_file(dir.join(key));
+        }
+
+        info!(
+            "Disk cache at {} loaded: {} entries, {} bytes",
+            dir.display(),
+            index.entries.len(),
+            index.total
+        );
+
+        Ok(Self {
+            dir,
+            capacity,
+            ttl,
+            index: Mutex::new(index),
+            next_tmp: AtomicU64::new(0),
+        })
+    }
+
+    /// 读取缓存的片段，过期或损坏的文件会被删除
+    pub async fn get(&self, url: &str) -> Option<CachedSegment> {
+        let key = cache_key(url);
+        if !self.index.lock().entries.contains_key(&key) {
+            return None;
+        }
+
+        let path = self.dir.join(&key);
+        let segment = match tokio::fs::read(&path).await {
+            Ok(data) => decode(&data, self.ttl),
+            Err(e) => {
+                debug!("Failed to read disk cache entry {}: {}", path.display(), e);
+                None
+            }
+        };
+
+        match segment {
+            Some(segment) => {
+                self.index.lock().touch(&key);
+                // 写回修改时间，重启后仍能按使用顺序淘汰
+                tokio::task::spawn_blocking(move || {
+                    if let Ok(file) = std::fs::File::options().write(true).open(&path) {
+                        let _ = file.set_modified(SystemTime::now());
+                    }
+                });
+                Some(segment)
+            }
+            None => {
+                self.index.lock().remove(&key);
+                let _ = tokio::fs::remove_file(&path).await;
+                None
+            }
+        }
+    }
+
+    /// 开始写入片段，已知长度超过容量时不写入
+    pub async fn writer(
+        self: &Arc<Self>,
+        url: &str,
+        content_type: Option<&str>,
+        content_length: Option<u64>,
+    ) -> Option<DiskWriter> {
+        if content_length.is_some_and(|length| length > self.capacity) {
+            return None;
+        }
+
+        let key = cache_key(url);
+        // 每个写入者使用独立的临时文件，同一 URL 并发写入时互不覆盖
+        let seq = self.next_tmp.fetch_add(1, Ordering::Relaxed);
+        let tmp_path = self.dir.join(format!("{}.{}.tmp", key, seq));
+        let header = format!("{} {}\n", unix_now(), content_type.unwrap_or_default());
+
+        let result = async {
+            let mut file = tokio::fs::File::create_new(&tmp_path).await?;
+            file.write_all(header.as_bytes()).await?;
+            Ok::<_, std::io::Error>(file)
+        }
+        .await;
+
+        match result {
+            Ok(file) => Some(DiskWriter {
+                cache: self.clone(),
+                key,
+                tmp_path,
+                file,
+                size: header.len() as u64,
+            }),
+            Err(e) => {
+                warn!("Failed to create disk cache file {}: {}", tmp_path.display(), e);
+                let _ = tokio::fs::remove_file(&tmp_path).await;
+                None
+            }
+        }
+    }
+
+    /// 文件数和总字节数
+    pub fn usage(&self) -> (u64, u64) {
+        let index = self.index.lock();
+        (index.entries.len() as u64, index.total)
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    /// 登记写完的文件并淘汰超出容量的旧文件
+    async fn commit(&self, key: String, size: u64) {
+        let evicted = {
+            let mut index = self.index.lock();
+            index.insert(key, size);
+            index.evict(self.capacity)
+        };
+        for key in evicted {
+            debug!("Evicting disk cache entry {}", key);
+            let _ = tokio::fs::remove_file(self.dir.join(key)).await;
+        }
+    }
+}
+
+/// 随上游数据写入磁盘缓存，`finish` 后才会被读取
+pub struct DiskWriter {
+    cache: Arc<DiskCache>,
+    key: String,
+    tmp_path: PathBuf,
+    file: tokio::fs::File,
+    size: u64,
+}
+
+impl DiskWriter {
+    /// 追加数据，写入失败或超过容量时返回 false，之后应调用 `abort`
+    pub async fn write(&mut self, chunk: &Bytes) -> bool {
+        self.size += chunk.len() as u64;
+        if self.size > self.cache.capacity {
+            return false;
+        }
+        match self.file.write_all(chunk).await {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Failed to write disk cache file {}: {}", self.tmp_path.display(), e);
+                false
+            }
+        }
+    }
+
+    /// 完成写入，把临时文件改名为正式文件
+    pub async fn finish(mut self) {
+        let final_path = self.cache.dir.join(&self.key);
+        let result = async {
+            self.file.flush().await?;
+            tokio::fs::rename(&self.tmp_path, &final_path).await
+        }
+        .await;
+
+        match result {
+            Ok(()) => self.cache.commit(self.key, self.size).await,
+            Err(e) => {
+                warn!("Failed to save disk cache file {}: {}", final_path.display(), e);
+                let _ = tokio::fs::remove_file(&self.tmp_path).await;
+            }
+        }
+    }
+
+    /// 放弃写入，删除临时文件
+    pub async fn abort(self) {
+        drop(self.file);
+        let _ = tokio::fs::remove_file(&self.tmp_path).await;
+    }
+}
+
+/// 缓存文件名：URL 的 SHA-256
+fn cache_key(url: &str) -> String {
+    Sha256::digest(url.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn is_key(name: &str) -> bool {
+    name.len() == 64 && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// 本缓存创建的临时文件名：`{key}.{序号}.tmp`
+fn is_tmp(name: &str) -> bool {
+    let Some((key, rest)) = name.split_once('.') else {
+        return false;
+    };
+    let Some(seq) = rest.strip_suffix(".tmp") else {
+        return false;
+    };
+    is_key(key) && !seq.is_empty() && seq.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// 解析缓存文件，过期时返回 None
+fn decode(data: &[u8], ttl: Duration) -> Option<CachedSegment> {
+    let newline = data.iter().position(|&b| b == b'\n')?;
+    let header = std::str::from_utf8(&data[..newline]).ok()?;
+    let (written_at, content_type) = header.split_once(' ')?;
+
+    let written_at: u64 = written_at.parse().ok()?;
+    if unix_now().saturating_sub(written_at) > ttl.as_secs() {
+        return None;
+    }
+
+    Some(CachedSegment {
+        content_type: Some(content_type.to_string()).filter(|v| !v.is_empty()),
+        body: Bytes::copy_from_slice(&data[newline + 1..]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("m3u_proxy_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    async fn store(cache: &Arc<DiskCache>, url: &str, body: &'static [u8]) {
+        let mut writer = cache.writer(url, Some("video/mp2t"), None).await.unwrap();
+        assert!(writer.write(&Bytes::from_static(body)).await);
+        writer.finish().await;
+    }
+
+    #[tokio::test]
+    async fn test_disk_cache_survives_restart_and_evicts_lru() {
+        let dir = temp_dir("disk_cache");
+        let ttl = Duration::from_secs(600);
+        // 每个文件约 100 字节（首行 + 64 字节内容），容量只够保存两个
+        let cache = Arc::new(DiskCache::open(&dir, 250, ttl).unwrap());
+
+        store(&cache, "http://example.com/1.ts", &[b'1'; 64]).await;
+        store(&cache, "http://example.com/2.ts", &[b'2'; 64]).await;
+        assert!(cache.get("http://example.com/1.ts").await.is_some());
+        store(&cache, "http://example.com/3.ts", &[b'3'; 64]).await;
+
+        // 2 最久未使用，被淘汰
+        assert!(cache.get("http://example.com/2.ts").await.is_none());
+        assert_eq!(cache.usage().0, 2);
+
+        drop(cache);
+        let cache = DiskCache::open(&dir, 250, ttl).unwrap();
+        let segment = cache.get("http://example.com/3.ts").await.unwrap();
+        assert_eq!(segment.content_type.as_deref(), Some("video/mp2t"));
+        assert_eq!(segment.body, Bytes::from_static(&[b'3'; 64]));
+        assert!(cache.get("http://example.com/1.ts").await.is_some());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_writers_do_not_share_tmp_file() {
+        let dir = temp_dir("disk_cache_tmp");
+        let ttl = Duration::from_secs(600);
+        let cache = Arc::new(DiskCache::open(&dir, 1024, ttl).unwrap());
+        let url = "http://example.com/1.ts";
+
+        let mut first = cache.writer(url, Some("video/mp2t"), None).await.unwrap();
+        let mut second = cache.writer(url, Some("video/mp2t"), None).await.unwrap();
+        assert!(first.write(&Bytes::from_static(b"aaaa")).await);
+        assert!(second.write(&Bytes::from_static(b"bb")).await);
+        first.finish().await;
+        assert_eq!(cache.get(url).await.unwrap().body, Bytes::from_static(b"aaaa"));
+        second.finish().await;
+        assert_eq!(cache.get(url).await.unwrap().body, Bytes::from_static(b"bb"));
+
+        // 重启时只清理本缓存的临时文件
+        let key = cache_key(url);
+        std::fs::write(dir.join(format!("{}.7.tmp", key)), b"partial").unwrap();
+        std::fs::write(dir.join("other.tmp"), b"keep").unwrap();
+        drop(cache);
+        let cache = DiskCache::open(&dir, 1024, ttl).unwrap();
+        assert!(!dir.join(format!("{}.7.tmp", key)).exists());
+        assert!(dir.join("other.tmp").exists());
+        assert_eq!(cache.usage().0, 1);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_decode_expired_entry() {
+        let fresh = format!("{} video/mp2t\nbody", unix_now());
+        assert!(decode(fresh.as_bytes(), Duration::from_secs(60)).is_some());
+
+        let expired = format!("{} \nbody", unix_now() - 120);
+        assert!(decode(expired.as_bytes(), Duration::from_secs(60)).is_none());
+        assert!(decode(b"garbage", Duration::from_secs(60)).is_none());
+    }
+}
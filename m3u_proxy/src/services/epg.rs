@@ -0,0 +1,454 @@
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use crate::error::{AppError, Result};
+use crate::models::{Channel, EpgChannel, NowNext, Programme};
+use crate::services::m3u_source::SourceLocation;
+use crate::services::xmltv::{XmltvData, XmltvParser};
+use crate::services::xmltv_writer::ChannelGuide;
+use crate::services::{ChannelManager, ProxyService};
+
+/// 归一化频道名称，用于 tvg-id / tvg-name / 频道名的模糊匹配
+fn normalize_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| !c.is_whitespace() && !matches!(c, '-' | '_' | '.'))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// 按 tvg-shift 平移节目时间
+fn shifted(programme: &Programme, shift: i64) -> Programme {
+    Programme {
+        start: programme.start.saturating_add(shift),
+        stop: programme.stop.saturating_add(shift),
+        ..programme.clone()
+    }
+}
+
+/// 筛选平移后与 `[from, to)` 有重叠的节目（节目列表的开始和结束时间都是递增的，见 `EpgIndex::build`）
+fn in_window(
+    list: &[Programme],
+    from: i64,
+    to: i64,
+    shift: i64,
+) -> impl Iterator<Item = Programme> + '_ {
+    // `from` / `to` 来自查询参数，可能是任意值
+    let (from, to) = (from.saturating_sub(shift), to.saturating_sub(shift));
+    let begin = list.partition_point(|p| p.stop <= from);
+    list[begin..]
+        .iter()
+        .take_while(move |p| p.start < to)
+        .filter(move |p| p.stop > from)
+        .map(move |p| shifted(p, shift))
+}
+
+/// 按 XMLTV 频道 ID 建立的节目索引
+#[derive(Default)]
+struct EpgIndex {
+    channels: HashMap<String, EpgChannel>,
+    /// 频道 ID -> 按开始时间排序的节目，结束时间也是递增的（可以按结束时间二分查找）
+    programmes: HashMap<String, Vec<Programme>>,
+    /// 归一化的频道 ID / 显示名称 -> 频道 ID
+    names: HashMap<String, String>,
+}
+
+impl EpgIndex {
+    /// 合并多个 XMLTV 文件，同一频道同一开始时间的节目以先加载的为准，
+    /// 完全落在前一个节目时间段内的节目（节目单之间或内部重叠）被去掉
+    fn build(sources: Vec<XmltvData>) -> Self {
+        let mut index = Self::default();
+
+        for data in sources {
+            for channel in data.channels {
+                index.channels.entry(channel.id.clone()).or_insert(channel);
+            }
+            for programme in data.programmes {
+                index
+                    .programmes
+                    .entry(programme.channel.clone())
+                    .or_default()
+                    .push(programme);
+            }
+        }
+
+        for list in index.programmes.values_mut() {
+            list.sort_by_key(|p| p.start);
+            list.dedup_by_key(|p| p.start);
+            // 去掉嵌套的节目后结束时间也是递增的
+            let mut last_stop = i64::MIN;
+            list.retain(|p| {
+                let keep = p.stop > last_stop;
+                if keep {
+                    last_stop = p.stop;
+                }
+                keep
+            });
+        }
+
+        for channel in index.channels.values() {
+            let names = std::iter::once(&channel.id).chain(&channel.display_names);
+            for name in names {
+                index
+                    .names
+                    .entry(normalize_name(name))
+                    .or_insert_with(|| channel.id.clone());
+            }
+        }
+        for id in index.programmes.keys() {
+            index
+                .names
+                .entry(normalize_name(id))
+                .or_insert_with(|| id.clone());
+        }
+
+        index
+    }
+
+    /// 查找频道对应的 XMLTV 频道 ID：先精确匹配 tvg-id，再依次模糊匹配 tvg-id、tvg-name 和频道名
+    fn resolve(&self, channel: &Channel) -> Option<&str> {
+        if let Some((id, _)) = self.programmes.get_key_value(&channel.tvg_id) {
+            return Some(id);
+        }
+
+        [
+            Some(channel.tvg_id.as_str()),
+            channel.tvg_name.as_deref(),
+            Some(channel.name.as_str()),
+        ]
+        .into_iter()
+        .flatten()
+        .map(normalize_name)
+        .filter(|name| !name.is_empty())
+        .find_map(|name| self.names.get(&name))
+        .map(String::as_str)
+    }
+
+    fn programmes_for(&self, channel: &Channel) -> &[Programme] {
+        self.resolve(channel)
+            .and_then(|id| self.programmes.get(id))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+}
+
+/// EPG 服务
+///
+/// 从 XMLTV 地址（配置项或播放列表头部的 `x-tvg-url`）加载节目单，并按频道提供查询
+pub struct EpgService {
+    index: RwLock<Arc<EpgIndex>>,
+    configured_urls: Vec<String>,
+    channel_manager: Arc<ChannelManager>,
+    proxy: Arc<ProxyService>,
+}
+
+impl EpgService {
+    /// 创建 EPG 服务（此时索引为空，需要调用 `reload` 加载）
+    pub fn new(
+        configured_urls: Vec<String>,
+        channel_manager: Arc<ChannelManager>,
+        proxy: Arc<ProxyService>,
+    ) -> Self {
+        Self {
+            index: RwLock::new(Arc::new(EpgIndex::default())),
+            configured_urls,
+            channel_manager,
+            proxy,
+        }
+    }
+
+    /// 实际使用的 XMLTV 地址，配置项优先于播放列表声明
+    pub fn urls(&self) -> Vec<String> {
+        if self.configured_urls.is_empty() {
+            self.channel_manager.get_playlist_info().epg_urls
+        } else {
+            self.configured_urls.clone()
+        }
+    }
+
+    /// 重新加载所有 XMLTV 地址并替换索引
+    ///
+    /// 部分地址失败时使用其余地址的数据；全部失败时保留原有索引
+    pub async fn reload(&self) -> Result<usize> {
+        let urls = self.urls();
+        if urls.is_empty() {
+            info!("No EPG source configured or declared by the playlist");
+            return Ok(0);
+        }
+
+        let mut loaded = Vec::new();
+        let mut last_error = None;
+        for url in &urls {
+            match self.load_url(url).await {
+                Ok(data) => {
+                    info!(
+                        "Loaded EPG from {}: {} channels, {} programmes",
+                        url,
+                        data.channels.len(),
+                        data.programmes.len()
+                    );
+                    loaded.push(data);
+                }
+                Err(e) => {
+                    warn!("Failed to load EPG from {}: {}", url, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        if loaded.is_empty()
+            && let Some(e) = last_error
+        {
+            return Err(e);
+        }
+
+        let index = EpgIndex::build(loaded);
+        let count = index.programmes.values().map(Vec::len).sum();
+        *self.index.write() = Arc::new(index);
+
+        Ok(count)
+    }
+
+    /// 下载或读取单个 XMLTV 文件并在阻塞线程中解析
+    async fn load_url(&self, url: &str) -> Result<XmltvData> {
+        let bytes = match SourceLocation::parse(url) {
+            SourceLocation::Remote(url) => self.proxy.fetch_bytes(&url).await?,
+            SourceLocation::File(path) => tokio::fs::read(path).await?.into(),
+        };
+
+        tokio::task::spawn_blocking(move || XmltvParser::parse_bytes(&bytes))
+            .await
+            .map_err(|e| AppError::Internal(format!("EPG parser task failed: {}", e)))?
+    }
+
+    /// 频道匹配到的 XMLTV 频道 ID
+    pub fn epg_channel_id(&self, channel: &Channel) -> Option<String> {
+        self.index.read().resolve(channel).map(str::to_string)
+    }
+
+    /// XMLTV 频道定义
+    pub fn get_epg_channel(&self, id: &str) -> Option<EpgChannel> {
+        self.index.read().channels.get(id).cloned()
+    }
+
+    /// 获取频道在 `[from, to)` 时间范围内播出的节目（已按频道的 tvg-shift 平移）
+    pub fn get_programmes(&self, channel: &Channel, from: i64, to: i64) -> Vec<Programme> {
+        let index = self.index.read();
+        in_window(index.programmes_for(channel), from, to, channel.tvg_shift()).collect()
+    }
+
+    /// 获取频道当前播出和下一个节目（已按频道的 tvg-shift 平移），没有匹配的节目单时返回 None
+    pub fn now_next(&self, channel: &Channel, now: i64) -> Option<NowNext> {
+        let index = self.index.read();
+        let list = index.programmes_for(channel);
+        if list.is_empty() {
+            return None;
+        }
+
+        let shift = channel.tvg_shift();
+        let guide_now = now - shift;
+        let current = list.partition_point(|p| p.stop <= guide_now);
+        let (now, next) = match list.get(current) {
+            Some(p) if p.start <= guide_now => (Some(p), list.get(current + 1)),
+            next => (None, next),
+        };
+
+        Some(NowNext {
+            now: now.map(|p| shifted(p, shift)),
+            next: next.map(|p| shifted(p, shift)),
+        })
+    }
+
+    /// 生成导出用的节目单，只包含匹配到 EPG 的频道
+    ///
+    /// 节目的频道 ID 替换为频道的稳定 ID，时间已按 tvg-shift 平移
+    pub fn guide(&self, channels: &[Channel], from: i64, to: i64) -> Vec<ChannelGuide> {
+        let index = self.index.read();
+
+        channels
+            .iter()
+            .filter_map(|channel| {
+                let id = index.resolve(channel)?;
+                let list = index
+                    .programmes
+                    .get(id)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default();
+                let programmes = in_window(list, from, to, channel.tvg_shift())
+                    .map(|p| Programme {
+                        channel: channel.id.clone(),
+                        ..p
+                    })
+                    .collect();
+
+                Some(ChannelGuide {
+                    channel: channel.clone(),
+                    icon: channel
+                        .logo
+                        .clone()
+                        .or_else(|| index.channels.get(id).and_then(|c| c.icon.clone())),
+                    programmes,
+                })
+            })
+            .collect()
+    }
+}
+
+/// 启动 EPG 加载任务
+///
+/// 启动后立即加载一次，之后按固定间隔刷新；间隔为 0 时只加载一次
+pub fn spawn_epg_refresher(epg: Arc<EpgService>, interval: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = (!interval.is_zero()).then(|| tokio::time::interval(interval));
+
+        loop {
+            if let Some(ticker) = ticker.as_mut() {
+                ticker.tick().await;
+            }
+
+            match epg.reload().await {
+                Ok(count) => info!("EPG index contains {} programmes", count),
+                Err(e) => error!("Failed to load EPG: {}, keeping previous index", e),
+            }
+
+            if ticker.is_none() {
+                break;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn programme(channel: &str, start: i64, stop: i64, title: &str) -> Programme {
+        Programme {
+            channel: channel.to_string(),
+            start,
+            stop,
+            title: title.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_epg_index_matching() {
+        let index = EpgIndex::build(vec![XmltvData {
+            channels: vec![EpgChannel {
+                id: "cctv1.cn".to_string(),
+                display_names: vec!["CCTV-1 综合".to_string()],
+                icon: None,
+            }],
+            programmes: vec![
+                programme("cctv1.cn", 200, 300, "B"),
+                programme("cctv1.cn", 100, 200, "A"),
+                programme("CCTV2", 100, 200, "C"),
+            ],
+        }]);
+
+        let by_id = Channel {
+            tvg_id: "cctv1.cn".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(index.resolve(&by_id), Some("cctv1.cn"));
+
+        let by_name = Channel {
+            name: "cctv1 综合".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(index.resolve(&by_name), Some("cctv1.cn"));
+
+        let by_tvg_name = Channel {
+            tvg_name: Some("cctv-2".to_string()),
+            name: "央视二套".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(index.resolve(&by_tvg_name), Some("CCTV2"));
+
+        let titles: Vec<_> = index
+            .programmes_for(&by_id)
+            .iter()
+            .map(|p| p.title.as_str())
+            .collect();
+        assert_eq!(titles, vec!["A", "B"]);
+    }
+
+    #[test]
+    fn test_overlapping_programmes() {
+        let index = EpgIndex::build(vec![
+            XmltvData {
+                channels: Vec::new(),
+                programmes: vec![
+                    programme("CCTV1", 0, 7200, "Long"),
+                    programme("CCTV1", 7200, 9000, "C"),
+                    programme("CCTV1", 9000, 10800, "D"),
+                ],
+            },
+            XmltvData {
+                channels: Vec::new(),
+                programmes: vec![
+                    programme("CCTV1", 1800, 3600, "Nested"),
+                    programme("CCTV1", 7200, 7800, "Duplicate"),
+                    programme("CCTV1", 8000, 9500, "Overlap"),
+                ],
+            },
+        ]);
+        let list = &index.programmes["CCTV1"];
+        let titles: Vec<_> = list.iter().map(|p| p.title.as_str()).collect();
+        assert_eq!(titles, vec!["Long", "C", "Overlap", "D"]);
+
+        let window: Vec<_> = in_window(list, 4000, 8500, 0).map(|p| p.title).collect();
+        assert_eq!(window, vec!["Long", "C", "Overlap"]);
+        let window: Vec<_> = in_window(list, 9200, 9300, 0).map(|p| p.title).collect();
+        assert_eq!(window, vec!["Overlap", "D"]);
+
+        // 极端的查询参数不会溢出
+        assert_eq!(in_window(list, i64::MIN, i64::MAX, 3600).count(), 4);
+        assert_eq!(in_window(list, i64::MAX - 1, i64::MAX, -3600).count(), 0);
+    }
+
+    #[test]
+    fn test_tvg_shift() {
+        let epg = EpgService::new(
+            Vec::new(),
+            Arc::new(ChannelManager::new()),
+            Arc::new(ProxyService::new(5).unwrap()),
+        );
+        *epg.index.write() = Arc::new(EpgIndex::build(vec![XmltvData {
+            channels: Vec::new(),
+            programmes: vec![
+                programme("CCTV1", 0, 3600, "A"),
+                programme("CCTV1", 3600, 7200, "B"),
+            ],
+        }]));
+
+        let mut channel = Channel {
+            tvg_id: "CCTV1".to_string(),
+            ..Default::default()
+        };
+        let now_next = epg.now_next(&channel, 1800).unwrap();
+        assert_eq!(now_next.now.unwrap().title, "A");
+
+        channel
+            .attributes
+            .insert("tvg-shift".to_string(), "+1".to_string());
+        let now_next = epg.now_next(&channel, 1800).unwrap();
+        assert!(now_next.now.is_none());
+        assert_eq!(now_next.next.unwrap().start, 3600);
+
+        let now_next = epg.now_next(&channel, 5400).unwrap();
+        let now = now_next.now.unwrap();
+        assert_eq!((now.title.as_str(), now.start, now.stop), ("A", 3600, 7200));
+
+        channel
+            .attributes
+            .insert("tvg-shift".to_string(), "-0.5".to_string());
+        let programmes = epg.get_programmes(&channel, 0, 3600);
+        assert_eq!(programmes[0].start, -1800);
+        assert_eq!(programmes[1].start, 1800);
+    }
+}
@@ -0,0 +1,204 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task::{JoinHandle, JoinSet};
+use tracing::{debug, info};
+use url::Url;
+
+use crate::error::{AppError, Result};
+use crate::models::{Channel, ChannelHealth, HealthStatus, StreamType};
+use crate::services::channel_manager::unix_now;
+use crate::services::proxy::UpstreamOptions;
+use crate::services::{ChannelManager, ProxyService};
+
+/// 频道健康检查器
+///
+/// 通过 `ProxyService` 请求每个上游 URL（使用频道的 User-Agent / Referer），
+/// HLS 频道会校验 `#EXTM3U` 头并拉取一个分片
+pub struct HealthChecker {
+    proxy: Arc<ProxyService>,
+    channel_manager: Arc<ChannelManager>,
+    concurrency: usize,
+    timeout: Duration,
+}
+
+impl HealthChecker {
+    /// 创建健康检查器，`concurrency` 为同时检查的频道数，`timeout` 为单个频道的检查时限
+    pub fn new(
+        proxy: Arc<ProxyService>,
+        channel_manager: Arc<ChannelManager>,
+        concurrency: usize,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            proxy,
+            channel_manager,
+            concurrency,
+            timeout,
+        }
+    }
+
+    /// 检查所有频道（相同 URL 只检查一次），返回在线和离线的数量
+    pub async fn check_all(self: &Arc<Self>) -> (usize, usize) {
+        let channels = self.channel_manager.get_unique_url_channels();
+        let semaphore = Arc::new(Semaphore::new(self.concurrency));
+        let mut tasks = JoinSet::new();
+
+        for channel in channels {
+            let Ok(permit) = semaphore.clone().acquire_owned().await else {
+                break;
+            };
+            let checker = self.clone();
+
+            tasks.spawn(async move {
+                let health = checker.check(&channel).await;
+                drop(permit);
+                (channel.url, health)
+            });
+        }
+
+        let (mut online, mut offline) = (0, 0);
+        let mut results = Vec::new();
+        while let Some(result) = tasks.join_next().await {
+            match result {
+                Ok((url, health)) => {
+                    if health.status == HealthStatus::Online {
+                        online += 1;
+                    } else {
+                        offline += 1;
+                    }
+                    results.push((url, health));
+                }
+                Err(_) => offline += 1,
+            }
+        }
+
+        // 所有结果一次写入，避免每个结果都锁住整个频道目录
+        self.channel_manager.record_health(results);
+        self.channel_manager.prune_health();
+        (online, offline)
+    }
+
+    /// 检查单个频道
+    pub async fn check(&self, channel: &Channel) -> ChannelHealth {
+        let result = tokio::time::timeout(self.timeout, self.probe(channel))
+            .await
+            .unwrap_or_else(|_| {
+                Err(AppError::ProxyError(format!(
+                    "Health check timed out after {:?}",
+                    self.timeout
+                )))
+            });
+
+        match result {
+            Ok(latency) => ChannelHealth {
+                status: HealthStatus::Online,
+                latency_ms: Some(latency.as_millis() as u64),
+                last_checked: unix_now(),
+                error: None,
+            },
+            Err(e) => {
+                debug!("Channel {} ({}) is offline: {}", channel.id, channel.url, e);
+                ChannelHealth {
+                    status: HealthStatus::Offline,
+                    latency_ms: None,
+                    last_checked: unix_now(),
+                    error: Some(e.to_string()),
+                }
+            }
+        }
+    }
+
+    /// 请求上游，返回首个请求的耗时
+    async fn probe(&self, channel: &Channel) -> Result<Duration> {
+        let options = UpstreamOptions::from_channel(channel);
+        let started = Instant::now();
+
+        if channel.stream_type != StreamType::HLS {
+            self.proxy.fetch_first_chunk(&channel.url, &options).await?;
+            return Ok(started.elapsed());
+        }
+
+        let mut base = Url::parse(&channel.url)?;
+        let mut playlist = self.proxy.fetch_text(&channel.url, &options).await?;
+        let latency = started.elapsed();
+        validate_playlist(&playlist)?;
+
+        // 主播放列表先取第一个子播放列表
+        if playlist.contains("#EXT-X-STREAM-INF") {
+            let variant = first_uri(&playlist)
+                .ok_or_else(|| AppError::InvalidM3U("Master playlist has no variants".into()))?;
+            base = base.join(variant)?;
+            playlist = self.proxy.fetch_text(base.as_str(), &options).await?;
+            validate_playlist(&playlist)?;
+        }
+
+        let segment = first_uri(&playlist)
+            .ok_or_else(|| AppError::InvalidM3U("Playlist has no segments".into()))?;
+        let segment_url = base.join(segment)?;
+        self.proxy
+            .fetch_first_chunk(segment_url.as_str(), &options)
+            .await?;
+
+        Ok(latency)
+    }
+}
+
+/// 校验 HLS 播放列表的 `#EXTM3U` 头
+fn validate_playlist(content: &str) -> Result<()> {
+    if content
+        .trim_start_matches('\u{feff}')
+        .trim_start()
+        .starts_with("#EXTM3U")
+    {
+        Ok(())
+    } else {
+        Err(AppError::InvalidM3U("Missing #EXTM3U header".to_string()))
+    }
+}
+
+/// 播放列表中的第一个 URI 行
+fn first_uri(content: &str) -> Option<&str> {
+    content
+        .lines()
+        .map(|line| line.trim_start_matches('\u{feff}').trim())
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+}
+
+/// 启动健康检查任务
+///
+/// 启动后立即检查一次，之后按固定间隔重复
+pub fn spawn_health_checker(checker: Arc<HealthChecker>, interval: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        info!("Checking channel health every {:?}", interval);
+
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+
+            let started = Instant::now();
+            let (online, offline) = checker.check_all().await;
+            info!(
+                "Health check finished in {:?}: {} online, {} offline",
+                started.elapsed(),
+                online,
+                offline
+            );
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_playlist_helpers() {
+        let master = "\u{feff}#EXTM3U\n#EXT-X-STREAM-INF:BANDWIDTH=800000\n\nlow/index.m3u8\n";
+        assert!(validate_playlist(master).is_ok());
+        assert_eq!(first_uri(master), Some("low/index.m3u8"));
+
+        assert!(validate_playlist("<html>404</html>").is_err());
+        assert_eq!(first_uri("#EXTM3U\n#EXT-X-ENDLIST\n"), None);
+    }
+}
@@ -1,22 +1,31 @@
 use crate::error::AppError;
+use crate::services::proxy_token::{ProxyTokens, TokenScope};
+use std::sync::Arc;
 use tracing::debug;
 use url::Url;
 
 /// M3U8 URL 重写器
 pub struct M3u8Rewriter {
-    proxy_base_url: String,
+    tokens: Arc<ProxyTokens>,
 }
 
 impl M3u8Rewriter {
-    /// 创建新的 M3U8 重写器
-    pub fn new(proxy_base_url: String) -> Self {
-        Self { proxy_base_url }
+    /// 创建新的 M3U8 重写器，代理地址使用 `tokens` 签发的令牌
+    pub fn new(tokens: Arc<ProxyTokens>) -> Self {
+        Self { tokens }
     }
 
     /// 重写 M3U8 内容中的 URL
     ///
-    /// 将 M3U8 文件中的所有 URL（包括播放列表和片段）重写为通过代理服务器访问
-    pub fn rewrite_m3u8(&self, content: &str, original_url: &str) -> Result<String, AppError> {
+    /// 将 M3U8 文件中的所有 URL（包括播放列表和片段）重写为通过代理服务器访问。
+    /// 代理地址的令牌按 `scope` 签发：记录频道 ID（后续请求沿用该频道的上游请求头）、
+    /// 调用方身份，开启客户端绑定时只对请求方地址有效
+    pub fn rewrite_m3u8(
+        &self,
+        content: &str,
+        original_url: &str,
+        scope: &TokenScope,
+    ) -> Result<String, AppError> {
         let mut result = String::new();
 
         // 解析原始 URL 以便处理相对路径
@@ -35,7 +44,7 @@ impl M3u8Rewriter {
 
             // 处理 #EXT-X-KEY 行中的 URI
             if trimmed.starts_with("#EXT-X-KEY") {
-                let rewritten = self.rewrite_key_line(trimmed, &base_url)?;
+                let rewritten = self.rewrite_key_line(trimmed, &base_url, scope)?;
                 result.push_str(&rewritten);
                 result.push('\n');
                 continue;
@@ -44,7 +53,7 @@ impl M3u8Rewriter {
             // 处理 URL 行
             if !trimmed.starts_with('#') {
                 let absolute_url = self.resolve_url(trimmed, &base_url)?;
-                let proxied_url = self.create_proxy_url(&absolute_url)?;
+                let proxied_url = self.create_proxy_url(&absolute_url, scope)?;
                 debug!("Rewriting URL: {} -> {}", trimmed, proxied_url);
                 result.push_str(&proxied_url);
                 result.push('\n');
@@ -57,14 +66,43 @@ impl M3u8Rewriter {
         Ok(result)
     }
 
+    /// 播放列表中引用的地址（子播放列表、片段和 #EXT-X-KEY），已转换为绝对地址
+    pub fn referenced_urls(&self, content: &str, original_url: &str) -> Vec<String> {
+        let Ok(base_url) = Url::parse(original_url) else {
+            return Vec::new();
+        };
+
+        content
+            .lines()
+            .map(str::trim)
+            .filter_map(|line| {
+                if line.starts_with("#EXT-X-KEY") {
+                    let start = line.find("URI=\"")? + 5;
+                    let end = line[start..].find('"')?;
+                    Some(&line[start..start + end])
+                } else if line.is_empty() || line.starts_with('#') {
+                    None
+                } else {
+                    Some(line)
+                }
+            })
+            .filter_map(|uri| self.resolve_url(uri, &base_url).ok())
+            .collect()
+    }
+
     /// 重写 #EXT-X-KEY 行中的 URI
-    fn rewrite_key_line(&self, line: &str, base_url: &Url) -> Result<String, AppError> {
+    fn rewrite_key_line(
+        &self,
+        line: &str,
+        base_url: &Url,
+        scope: &TokenScope,
+    ) -> Result<String, AppError> {
         if let Some(uri_start) = line.find("URI=\"") {
             let uri_start = uri_start + 5; // "URI=\"" 的长度
             if let Some(uri_end) = line[uri_start..].find('"') {
                 let uri = &line[uri_start..uri_start + uri_end];
                 let absolute_url = self.resolve_url(uri, base_url)?;
-                let proxied_url = self.create_proxy_url(&absolute_url)?;
+                let proxied_url = self.create_proxy_url(&absolute_url, scope)?;
 
                 let mut result = String::from(&line[..uri_start]);
                 result.push_str(&proxied_url);
@@ -91,8 +129,12 @@ impl M3u8Rewriter {
         Ok(absolute.to_string())
     }
 
-    /// 创建代理 URL
-    fn create_proxy_url(&self, original_url: &str) -> Result<String, AppError> {
+    /// 创建代理 URL，上游地址和频道 ID 保存在签名令牌中，不出现在地址里
+    pub fn create_proxy_url(
+        &self,
+        original_url: &str,
+        scope: &TokenScope,
+    ) -> Result<String, AppError> {
         // 判断是播放列表还是片段
         let endpoint = if original_url.ends_with(".m3u8") || original_url.contains(".m3u8?") {
             "playlist"
@@ -100,22 +142,45 @@ impl M3u8Rewriter {
             "segment"
         };
 
-        // URL 编码原始 URL
-        let encoded_url = urlencoding::encode(original_url);
+        self.proxy_url(endpoint, original_url, scope)
+    }
+
+    /// 创建播放列表代理 URL（频道的 HLS 地址不一定以 .m3u8 结尾）
+    pub fn create_playlist_url(
+        &self,
+        original_url: &str,
+        scope: &TokenScope,
+    ) -> Result<String, AppError> {
+        self.proxy_url("playlist", original_url, scope)
+    }
+
+    fn proxy_url(
+        &self,
+        endpoint: &str,
+        original_url: &str,
+        scope: &TokenScope,
+    ) -> Result<String, AppError> {
+        let token = self.tokens.issue(original_url, scope)?;
 
         // 使用相对路径，让浏览器基于当前页面的 origin 来请求
         // 这样可以通过 Vite 代理或其他前端代理转发到后端
-        Ok(format!("/api/proxy/{}?url={}", endpoint, encoded_url))
+        Ok(format!("/api/proxy/{}?t={}", endpoint, token))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::time::Duration;
+
+    fn rewriter() -> M3u8Rewriter {
+        let tokens = ProxyTokens::new(b"secret", Duration::from_secs(60), false);
+        M3u8Rewriter::new(Arc::new(tokens))
+    }
 
     #[test]
     fn test_rewrite_simple_m3u8() {
-        let rewriter = M3u8Rewriter::new("http://localhost:8006".to_string());
+        let rewriter = rewriter();
         let content = r#"#EXTM3U
 #EXT-X-VERSION:3
 #EXT-X-TARGETDURATION:10
@@ -125,16 +190,32 @@ segment1.ts
 segment2.ts
 #EXT-X-ENDLIST"#;
 
-        let result = rewriter.rewrite_m3u8(content, "http://example.com/playlist.m3u8").unwrap();
-
-        assert!(result.contains("/api/proxy/segment?url="));
-        assert!(result.contains("segment1.ts"));
-        assert!(result.contains("segment2.ts"));
+        let scope = TokenScope {
+            channel_id: Some("ch_1"),
+            subject: Some("alice"),
+            client: None,
+        };
+        let result = rewriter
+            .rewrite_m3u8(content, "http://example.com/playlist.m3u8", &scope)
+            .unwrap();
+
+        // 上游地址只出现在令牌里
+        assert!(!result.contains("example.com"));
+        let tokens: Vec<&str> = result
+            .lines()
+            .filter_map(|line| line.strip_prefix("/api/proxy/segment?t="))
+            .collect();
+        assert_eq!(tokens.len(), 2);
+
+        let target = rewriter.tokens.verify(tokens[0], None).unwrap();
+        assert_eq!(target.url, "http://example.com/segment1.ts");
+        assert_eq!(target.channel_id.as_deref(), Some("ch_1"));
+        assert_eq!(target.subject.as_deref(), Some("alice"));
     }
 
     #[test]
     fn test_resolve_relative_url() {
-        let rewriter = M3u8Rewriter::new("http://localhost:8006".to_string());
+        let rewriter = rewriter();
         let base_url = Url::parse("http://example.com/path/playlist.m3u8").unwrap();
 
         let result = rewriter.resolve_url("segment.ts", &base_url).unwrap();
@@ -1,89 +1,431 @@
-use crate::error::{AppError, Result};
-use crate::models::Channel;
-use regex::Regex;
+use crate::error::Result;
+use crate::models::{Catchup, Channel, ParseIssue, ParseIssueKind};
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
+use url::Url;
+
+/// 频道未设置时从 `#EXTM3U` 头部继承的属性
+const INHERITED_ATTRIBUTES: [&str; 5] = [
+    "tvg-shift",
+    "catchup",
+    "catchup-source",
+    "catchup-days",
+    "catchup-correction",
+];
+
+/// 播放列表解析结果
+#[derive(Debug, Default)]
+pub struct ParsedPlaylist {
+    /// `#EXTM3U` 行上的属性（x-tvg-url 等）
+    pub header: BTreeMap<String, String>,
+    pub channels: Vec<Channel>,
+    pub issues: Vec<ParseIssue>,
+}
+
+/// 解析后的 EXTINF 行
+struct ExtInf {
+    attributes: Vec<(String, String)>,
+    title: String,
+}
+
+/// 频道条目中 EXTINF 之外的指令
+#[derive(Default)]
+struct Directives {
+    vlc_options: BTreeMap<String, String>,
+    kodi_props: BTreeMap<String, String>,
+    group: Option<String>,
+}
 
 pub struct M3uParser;
 
 impl M3uParser {
     /// 解析 M3U 文件
-    pub fn parse_file(path: &str) -> Result<Vec<Channel>> {
-        let content = fs::read_to_string(path)
-            .map_err(|e| AppError::Io(e))?;
+    pub fn parse_file(path: &str) -> Result<ParsedPlaylist> {
+        let content = fs::read_to_string(path)?;
+
+        Ok(Self::parse(&content))
+    }
 
-        Self::parse_content(&content)
+    /// 解析 M3U 内容，同时返回头部属性和逐行的解析问题
+    ///
+    /// `#EXTVLCOPT`、`#KODIPROP`、`#EXTGRP` 指令归属于同一个 URL 之前的频道条目，
+    /// 可以出现在 `#EXTINF` 之前或之后。频道 ID 为空，由调用方按来源调用 `assign_ids` 分配
+    pub fn parse(content: &str) -> ParsedPlaylist {
+        let mut playlist = Self::scan(content);
+        Self::inherit_header_attributes(&playlist.header, &mut playlist.channels);
+        for channel in &mut playlist.channels {
+            channel.catchup = Catchup::from_attributes(&channel.attributes);
+        }
+
+        playlist
     }
 
-    /// 解析 M3U 内容
-    pub fn parse_content(content: &str) -> Result<Vec<Channel>> {
+    /// 频道未设置 tvg-shift、catchup 等属性时使用头部的默认值
+    fn inherit_header_attributes(header: &BTreeMap<String, String>, channels: &mut [Channel]) {
+        for key in INHERITED_ATTRIBUTES {
+            let Some(value) = header.get(key) else {
+                continue;
+            };
+
+            for channel in channels.iter_mut() {
+                channel
+                    .attributes
+                    .entry(key.to_string())
+                    .or_insert_with(|| value.clone());
+            }
+        }
+    }
+
+    /// 逐行扫描 M3U 内容
+    fn scan(content: &str) -> ParsedPlaylist {
+        let mut header = BTreeMap::new();
         let mut channels = Vec::new();
+        let mut issues = Vec::new();
+        let mut pending: Option<(usize, ExtInf)> = None;
+        let mut directives = Directives::default();
+        let mut seen_urls: HashMap<String, usize> = HashMap::new();
+        let mut header_checked = false;
+
+        let mut issue = |line: usize, kind: ParseIssueKind, message: String, skipped: bool| {
+            issues.push(ParseIssue {
+                line,
+                kind,
+                message,
+                skipped,
+            });
+        };
 
-        // EXTINF 行的正则表达式
-        // 格式: #EXTINF:-1 tvg-id="..." tvg-name="..." tvg-logo="..." group-title="...",频道名称
-        let extinf_regex = Regex::new(
-            r#"#EXTINF:-1\s+(?:tvg-id="([^"]*)"\s*)?(?:tvg-name="([^"]*)"\s*)?(?:tvg-logo="([^"]*)"\s*)?(?:group-title="([^"]*)"\s*)?,(.+)"#
-        ).map_err(|e| AppError::InvalidM3U(format!("Invalid regex: {}", e)))?;
+        for (index, line) in content.lines().enumerate() {
+            let line_no = index + 1;
+            let line = line.trim().trim_start_matches('\u{feff}');
 
-        let lines: Vec<&str> = content.lines().collect();
-        let mut i = 0;
+            if line.is_empty() {
+                continue;
+            }
 
-        while i < lines.len() {
-            let line = lines[i].trim();
+            if !header_checked {
+                header_checked = true;
+                if !line.starts_with("#EXTM3U") {
+                    issue(
+                        line_no,
+                        ParseIssueKind::MissingHeader,
+                        "Playlist does not start with #EXTM3U".to_string(),
+                        false,
+                    );
+                }
+            }
 
-            // 跳过空行和注释（除了 EXTINF）
-            if line.is_empty() || (line.starts_with('#') && !line.starts_with("#EXTINF")) {
-                i += 1;
+            if let Some(attributes) = line.strip_prefix("#EXTM3U") {
+                let mut errors = Vec::new();
+                let (pairs, _) = Self::tokenize_attributes(attributes, &mut errors);
+                header.extend(pairs);
+                for error in errors {
+                    issue(line_no, ParseIssueKind::InvalidAttribute, error, false);
+                }
                 continue;
             }
 
-            // 如果是 EXTINF 行
-            if line.starts_with("#EXTINF") {
-                if let Some(caps) = extinf_regex.captures(line) {
-                    // 下一行应该是 URL
-                    i += 1;
-                    if i < lines.len() {
-                        let url = lines[i].trim();
-
-                        if !url.is_empty() && !url.starts_with('#') {
-                            let tvg_id = caps.get(1).map(|m| m.as_str().to_string()).unwrap_or_default();
-                            let _tvg_name = caps.get(2).map(|m| m.as_str().to_string()).unwrap_or_default();
-                            let logo = caps.get(3).map(|m| m.as_str().to_string()).filter(|s| !s.is_empty());
-                            let group = caps.get(4).map(|m| m.as_str().to_string()).unwrap_or_else(|| "未分类".to_string());
-                            let name = caps.get(5).map(|m| m.as_str().trim().to_string()).unwrap_or_else(|| "未命名频道".to_string());
-
-                            // 生成唯一 ID（始终使用索引确保唯一性）
-                            let id = format!("channel_{}", channels.len());
-
-                            let stream_type = Channel::detect_stream_type(url);
-
-                            channels.push(Channel {
-                                id,
-                                tvg_id,
-                                name,
-                                logo,
-                                group,
-                                url: url.to_string(),
-                                stream_type,
-                            });
-                        }
+            if let Some(extinf) = line.strip_prefix("#EXTINF:") {
+                if let Some((pending_line, _)) = pending.take() {
+                    issue(
+                        pending_line,
+                        ParseIssueKind::MissingUrl,
+                        "EXTINF is not followed by a URL".to_string(),
+                        true,
+                    );
+                }
+
+                let mut errors = Vec::new();
+                let parsed = Self::parse_extinf(extinf, &mut errors);
+                for error in errors {
+                    issue(line_no, ParseIssueKind::InvalidAttribute, error, false);
+                }
+                if parsed.title.is_empty() {
+                    issue(
+                        line_no,
+                        ParseIssueKind::MissingName,
+                        "EXTINF has no channel name after the comma".to_string(),
+                        false,
+                    );
+                }
+
+                pending = Some((line_no, parsed));
+                continue;
+            }
+
+            if let Some(option) = line.strip_prefix("#EXTVLCOPT:") {
+                match Self::parse_directive(option) {
+                    Some((key, value)) => {
+                        directives.vlc_options.insert(key, value);
+                    }
+                    None => issue(
+                        line_no,
+                        ParseIssueKind::InvalidDirective,
+                        format!("Invalid #EXTVLCOPT: {}", option),
+                        false,
+                    ),
+                }
+                continue;
+            }
+
+            if let Some(prop) = line.strip_prefix("#KODIPROP:") {
+                match Self::parse_directive(prop) {
+                    Some((key, value)) => {
+                        directives.kodi_props.insert(key, value);
                     }
+                    None => issue(
+                        line_no,
+                        ParseIssueKind::InvalidDirective,
+                        format!("Invalid #KODIPROP: {}", prop),
+                        false,
+                    ),
                 }
+                continue;
+            }
+
+            if let Some(group) = line.strip_prefix("#EXTGRP:") {
+                directives.group = Some(group.trim().to_string()).filter(|g| !g.is_empty());
+                continue;
+            }
+
+            // 跳过其他注释
+            if line.starts_with('#') {
+                continue;
+            }
+
+            // URL 行：与前面的 EXTINF 和指令组成一个频道
+            let entry_directives = std::mem::take(&mut directives);
+            let Some((_, extinf)) = pending.take() else {
+                issue(
+                    line_no,
+                    ParseIssueKind::MissingExtinf,
+                    "URL is not preceded by #EXTINF".to_string(),
+                    true,
+                );
+                continue;
+            };
+
+            if Url::parse(line).is_err() {
+                issue(
+                    line_no,
+                    ParseIssueKind::InvalidUrl,
+                    format!("Invalid URL: {}", line),
+                    true,
+                );
+                continue;
             }
 
-            i += 1;
+            if let Some(first_line) = seen_urls.get(line) {
+                issue(
+                    line_no,
+                    ParseIssueKind::DuplicateUrl,
+                    format!("URL already used on line {}", first_line),
+                    false,
+                );
+            } else {
+                seen_urls.insert(line.to_string(), line_no);
+            }
+
+            channels.push(Self::build_channel(extinf, entry_directives, line));
+        }
+
+        if let Some((pending_line, _)) = pending {
+            issue(
+                pending_line,
+                ParseIssueKind::MissingUrl,
+                "EXTINF is not followed by a URL".to_string(),
+                true,
+            );
+        }
+
+        ParsedPlaylist {
+            header,
+            channels,
+            issues,
         }
+    }
+
+    /// 为频道分配稳定 ID
+    ///
+    /// 属性完全相同的频道按出现顺序追加 `-2`、`-3` 后缀
+    pub fn assign_ids(channels: &mut [Channel], namespace: &str) {
+        let mut seen: HashMap<String, usize> = HashMap::new();
 
-        if channels.is_empty() {
-            return Err(AppError::InvalidM3U("No valid channels found in M3U file".to_string()));
+        for channel in channels.iter_mut() {
+            let base = Channel::stable_id(namespace, &channel.tvg_id, &channel.name, &channel.url);
+            let occurrence = seen.entry(base.clone()).or_insert(0);
+            *occurrence += 1;
+
+            channel.id = if *occurrence == 1 {
+                base
+            } else {
+                format!("{}-{}", base, occurrence)
+            };
         }
+    }
 
-        Ok(channels)
+    /// 解析 `#EXTINF:` 之后的内容：时长、属性列表和逗号后的频道名称
+    fn parse_extinf(content: &str, errors: &mut Vec<String>) -> ExtInf {
+        // 跳过时长（-1、0、10.5 等）
+        let rest = content.trim_start();
+        let duration_end = rest
+            .find(|c: char| c.is_whitespace() || c == ',')
+            .unwrap_or(rest.len());
+        let (attributes, title) = Self::tokenize_attributes(&rest[duration_end..], errors);
+
+        ExtInf {
+            attributes,
+            title: title.map(|t| t.trim().to_string()).unwrap_or_default(),
+        }
+    }
+
+    /// 解析 `key="value"` 形式的属性列表
+    ///
+    /// 属性顺序任意，值可以用双引号、单引号或不加引号；键统一转为小写。
+    /// 遇到引号外的逗号时停止，并返回逗号之后的剩余内容。无法解析的部分写入 `errors`
+    pub fn tokenize_attributes<'a>(
+        input: &'a str,
+        errors: &mut Vec<String>,
+    ) -> (Vec<(String, String)>, Option<&'a str>) {
+        let mut attributes = Vec::new();
+        let bytes = input.as_bytes();
+        let mut pos = 0;
+
+        loop {
+            while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if pos >= bytes.len() {
+                return (attributes, None);
+            }
+            if bytes[pos] == b',' {
+                return (attributes, Some(&input[pos + 1..]));
+            }
+
+            // 读取键
+            let key_start = pos;
+            while pos < bytes.len()
+                && !bytes[pos].is_ascii_whitespace()
+                && bytes[pos] != b'='
+                && bytes[pos] != b','
+            {
+                pos += 1;
+            }
+            let key = input[key_start..pos].to_lowercase();
+            if key.is_empty() && pos < bytes.len() && bytes[pos] == b'=' {
+                errors.push("Attribute value without a name".to_string());
+            }
+
+            if pos >= bytes.len() || bytes[pos] != b'=' {
+                // 没有值的独立标记
+                attributes.push((key, String::new()));
+                continue;
+            }
+            pos += 1;
+
+            // 读取值
+            let value = match bytes.get(pos) {
+                Some(&quote) if quote == b'"' || quote == b'\'' => {
+                    let value_start = pos + 1;
+                    match input[value_start..].find(quote as char) {
+                        Some(len) => {
+                            pos = value_start + len + 1;
+                            &input[value_start..value_start + len]
+                        }
+                        None => {
+                            // 引号未闭合，取到行尾
+                            errors.push(format!("Unclosed quote in value of {}", key));
+                            pos = bytes.len();
+                            &input[value_start..]
+                        }
+                    }
+                }
+                _ => {
+                    let value_start = pos;
+                    while pos < bytes.len()
+                        && !bytes[pos].is_ascii_whitespace()
+                        && bytes[pos] != b','
+                    {
+                        pos += 1;
+                    }
+                    &input[value_start..pos]
+                }
+            };
+
+            attributes.push((key, value.to_string()));
+        }
+    }
+
+    /// 解析 `key=value` 形式的指令（`#EXTVLCOPT` / `#KODIPROP`）
+    fn parse_directive(content: &str) -> Option<(String, String)> {
+        let (key, value) = content.split_once('=')?;
+        let key = key.trim().to_lowercase();
+        if key.is_empty() {
+            return None;
+        }
+
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(value);
+
+        Some((key, value.to_string()))
+    }
+
+    /// 根据 EXTINF 属性、附加指令和 URL 构建频道
+    fn build_channel(extinf: ExtInf, directives: Directives, url: &str) -> Channel {
+        let ExtInf { attributes, title } = extinf;
+        let mut tvg_id = String::new();
+        let mut tvg_name = None;
+        let mut logo = None;
+        let mut group = None;
+        let mut extra = BTreeMap::new();
+
+        for (key, value) in attributes {
+            match key.as_str() {
+                "tvg-id" => tvg_id = value,
+                "tvg-name" => tvg_name = Some(value).filter(|v| !v.is_empty()),
+                "tvg-logo" => logo = Some(value).filter(|v| !v.is_empty()),
+                "group-title" => group = Some(value).filter(|v| !v.is_empty()),
+                _ => {
+                    extra.insert(key, value);
+                }
+            }
+        }
+
+        let name = if !title.is_empty() {
+            title
+        } else {
+            tvg_name.clone().unwrap_or_else(|| "未命名频道".to_string())
+        };
+
+        Channel {
+            id: String::new(),
+            tvg_id,
+            tvg_name,
+            name,
+            logo,
+            group: group
+                .or(directives.group)
+                .unwrap_or_else(|| "未分类".to_string()),
+            url: url.to_string(),
+            backup_urls: Vec::new(),
+            stream_type: Channel::detect_stream_type(url),
+            source: String::new(),
+            attributes: extra,
+            vlc_options: directives.vlc_options,
+            kodi_props: directives.kodi_props,
+            catchup: None,
+            health: None,
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::CatchupMode;
 
     #[test]
     fn test_parse_extinf() {
@@ -96,14 +438,199 @@ http://gslbserv.itv.cmvideo.cn:80/3000000010000005180/index.m3u8?channel-id=Fifa
 http://example.com/cctv1.m3u8
 "#;
 
-        let result = M3uParser::parse_content(content);
-        assert!(result.is_ok());
-
-        let channels = result.unwrap();
+        let ParsedPlaylist {
+            header,
+            channels,
+            issues,
+        } = M3uParser::parse(content);
+        assert!(issues.is_empty());
+        assert_eq!(
+            header.get("x-tvg-url").map(String::as_str),
+            Some("https://epg-1.iill.top/epg.xml")
+        );
         assert_eq!(channels.len(), 2);
 
         assert_eq!(channels[0].tvg_id, "咪咕体育");
         assert_eq!(channels[0].name, "咪咕直播 𝟜𝕂-𝟙「移动」");
         assert_eq!(channels[0].group, "•咪咕「移动」");
     }
+
+    #[test]
+    fn test_stable_ids() {
+        let original = r#"#EXTM3U
+#EXTINF:-1 tvg-id="CCTV1" tvg-name="CCTV1" group-title="央视",CCTV-1 综合
+http://example.com/cctv1.m3u8
+#EXTINF:-1 tvg-id="CCTV1" tvg-name="CCTV1" group-title="央视",CCTV-1 综合
+http://example.com/cctv1.m3u8
+"#;
+        let inserted = r#"#EXTM3U
+#EXTINF:-1 tvg-id="CCTV2" tvg-name="CCTV2" group-title="央视",CCTV-2 财经
+http://example.com/cctv2.m3u8
+#EXTINF:-1 tvg-id="CCTV1" tvg-name="CCTV1" group-title="央视",CCTV-1 综合
+http://example.com/cctv1.m3u8
+#EXTINF:-1 tvg-id="CCTV1" tvg-name="CCTV1" group-title="央视",CCTV-1 综合
+http://example.com/cctv1.m3u8
+"#;
+
+        let mut before = M3uParser::parse(original).channels;
+        let mut after = M3uParser::parse(inserted).channels;
+        M3uParser::assign_ids(&mut before, "main");
+        M3uParser::assign_ids(&mut after, "main");
+
+        assert_eq!(before[1].id, format!("{}-2", before[0].id));
+        assert_eq!(before[0].id, after[1].id);
+        assert_eq!(before[1].id, after[2].id);
+    }
+
+    #[test]
+    fn test_tolerant_extinf() {
+        let content = r#"#EXTM3U
+#EXTINF:10 group-title='体育' tvg-chno=5 tvg-ID="CCTV5" catchup="default" tvg-country=CN,CCTV-5, 体育
+http://example.com/cctv5.m3u8
+#EXTINF:-1,无属性频道
+http://example.com/plain.m3u8
+"#;
+
+        let channels = M3uParser::parse(content).channels;
+        assert_eq!(channels.len(), 2);
+
+        assert_eq!(channels[0].tvg_id, "CCTV5");
+        assert_eq!(channels[0].name, "CCTV-5, 体育");
+        assert_eq!(channels[0].group, "体育");
+        assert_eq!(channels[0].attributes.get("tvg-chno").map(String::as_str), Some("5"));
+        assert_eq!(channels[0].attributes.get("catchup").map(String::as_str), Some("default"));
+        assert_eq!(channels[0].attributes.get("tvg-country").map(String::as_str), Some("CN"));
+
+        assert_eq!(channels[1].name, "无属性频道");
+        assert_eq!(channels[1].group, "未分类");
+    }
+
+    #[test]
+    fn test_channel_directives() {
+        let content = r#"#EXTM3U
+#EXTINF:-1 tvg-id="news",新闻频道
+#EXTVLCOPT:http-user-agent=Mozilla/5.0 (X11; Linux)
+#EXTVLCOPT:http-referrer=https://example.com/
+#KODIPROP:inputstream.adaptive.license_type=clearkey
+#EXTGRP:新闻
+http://example.com/news.m3u8
+#EXTVLCOPT:http-user-agent=Other
+#EXTINF:-1 group-title="体育",体育频道
+http://example.com/sports.m3u8
+"#;
+
+        let channels = M3uParser::parse(content).channels;
+        assert_eq!(channels.len(), 2);
+
+        assert_eq!(channels[0].group, "新闻");
+        assert_eq!(channels[0].user_agent(), Some("Mozilla/5.0 (X11; Linux)"));
+        assert_eq!(channels[0].referrer(), Some("https://example.com/"));
+        assert_eq!(
+            channels[0]
+                .kodi_props
+                .get("inputstream.adaptive.license_type")
+                .map(String::as_str),
+            Some("clearkey")
+        );
+
+        assert_eq!(channels[1].group, "体育");
+        assert_eq!(channels[1].user_agent(), Some("Other"));
+        assert_eq!(channels[1].referrer(), None);
+    }
+
+    #[test]
+    fn test_parse_issues() {
+        let content = r#"#EXTM3U
+#EXTINF:-1 tvg-id="a",频道A
+#EXTINF:-1 tvg-id="b" tvg-logo="http://example.com/b.png,频道B
+http://example.com/b.m3u8
+http://example.com/orphan.m3u8
+#EXTVLCOPT:no-value
+#EXTINF:-1 tvg-id="c",
+http://example.com/b.m3u8
+#EXTINF:-1,坏地址
+not a url
+"#;
+
+        let ParsedPlaylist {
+            channels, issues, ..
+        } = M3uParser::parse(content);
+        assert_eq!(channels.len(), 2);
+
+        let found: Vec<(usize, ParseIssueKind, bool)> =
+            issues.iter().map(|i| (i.line, i.kind, i.skipped)).collect();
+        assert_eq!(
+            found,
+            vec![
+                (2, ParseIssueKind::MissingUrl, true),
+                (3, ParseIssueKind::InvalidAttribute, false),
+                (3, ParseIssueKind::MissingName, false),
+                (5, ParseIssueKind::MissingExtinf, true),
+                (6, ParseIssueKind::InvalidDirective, false),
+                (7, ParseIssueKind::MissingName, false),
+                (8, ParseIssueKind::DuplicateUrl, false),
+                (10, ParseIssueKind::InvalidUrl, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_header_defaults() {
+        let content = r#"#EXTM3U url-tvg="http://epg.example.com/a.xml" tvg-shift="-1" catchup="append"
+#EXTINF:-1 tvg-id="a",频道A
+http://example.com/a.m3u8
+#EXTINF:-1 tvg-id="b" tvg-shift="2",频道B
+http://example.com/b.m3u8
+"#;
+
+        let playlist = M3uParser::parse(content);
+        assert_eq!(
+            playlist.header.get("url-tvg").map(String::as_str),
+            Some("http://epg.example.com/a.xml")
+        );
+
+        let shift = |i: usize| playlist.channels[i].attributes.get("tvg-shift").cloned();
+        assert_eq!(shift(0).as_deref(), Some("-1"));
+        assert_eq!(shift(1).as_deref(), Some("2"));
+        assert_eq!(
+            playlist.channels[1].attributes.get("catchup").map(String::as_str),
+            Some("append")
+        );
+    }
+
+    #[test]
+    fn test_catchup_attributes() {
+        let content = r#"#EXTM3U catchup="shift" catchup-days="3"
+#EXTINF:-1 tvg-id="a",频道A
+http://example.com/a.m3u8
+#EXTINF:-1 catchup="default" catchup-days="7" catchup-source="http://example.com/b.m3u8?start={utc}&end={utcend}",频道B
+http://example.com/b.m3u8
+#EXTINF:-1 catchup="append" catchup-source="",频道C
+http://example.com/c.m3u8
+#EXTINF:-1 catchup="flussonic",频道D
+http://example.com/d.m3u8
+"#;
+
+        let playlist = M3uParser::parse(content);
+        let catchup = |i: usize| playlist.channels[i].catchup.clone();
+
+        assert_eq!(
+            catchup(0),
+            Some(Catchup {
+                mode: CatchupMode::Shift,
+                days: Some(3),
+                source: None,
+            })
+        );
+        assert_eq!(
+            catchup(1),
+            Some(Catchup {
+                mode: CatchupMode::Default,
+                days: Some(7),
+                source: Some("http://example.com/b.m3u8?start={utc}&end={utcend}".to_string()),
+            })
+        );
+        assert_eq!(catchup(2), None);
+        assert_eq!(catchup(3), None);
+    }
 }
@@ -0,0 +1,313 @@
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use crate::config::SourceConfig;
+use crate::error::{AppError, Result};
+use crate::models::ParseReport;
+use crate::services::channel_manager::unix_now;
+use crate::services::m3u_parser::ParsedPlaylist;
+use crate::services::proxy::FetchOutcome;
+use crate::services::{ChannelManager, M3uParser, ProxyService};
+
+/// M3U 来源位置
+#[derive(Debug, Clone, PartialEq)]
+pub enum SourceLocation {
+    /// 本地文件路径
+    File(String),
+    /// HTTP(S) 订阅地址
+    Remote(String),
+}
+
+impl SourceLocation {
+    /// 根据前缀判断是本地路径还是远程地址
+    pub fn parse(location: &str) -> Self {
+        let lower = location.to_lowercase();
+        if lower.starts_with("http://") || lower.starts_with("https://") {
+            SourceLocation::Remote(location.to_string())
+        } else {
+            SourceLocation::File(location.to_string())
+        }
+    }
+}
+
+/// 条件请求校验信息（ETag / Last-Modified）
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+/// M3U 播放列表来源
+///
+/// 远程订阅会把最近一次解析成功的内容保存在数据目录中，上游不可用时可以从本地副本启动
+pub struct M3uSource {
+    name: String,
+    group_prefix: Option<String>,
+    location: SourceLocation,
+    cache_path: PathBuf,
+    meta_path: PathBuf,
+    validators: Mutex<Validators>,
+    proxy: Arc<ProxyService>,
+}
+
+impl M3uSource {
+    /// 创建新的播放列表来源
+    pub fn new(config: &SourceConfig, data_dir: &str, proxy: Arc<ProxyService>) -> Self {
+        let data_dir = Path::new(data_dir);
+
+        Self {
+            name: config.name.clone(),
+            group_prefix: config.group_prefix.clone().filter(|p| !p.is_empty()),
+            location: SourceLocation::parse(&config.location),
+            cache_path: data_dir.join(format!("{}.m3u", config.name)),
+            meta_path: data_dir.join(format!("{}.meta.json", config.name)),
+            validators: Mutex::new(Validators::default()),
+            proxy,
+        }
+    }
+
+    /// 来源名称
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// 是否为远程订阅
+    pub fn is_remote(&self) -> bool {
+        matches!(self.location, SourceLocation::Remote(_))
+    }
+
+    /// 来源描述（路径或 URL）
+    pub fn location(&self) -> &str {
+        match &self.location {
+            SourceLocation::File(path) => path,
+            SourceLocation::Remote(url) => url,
+        }
+    }
+
+    /// 启动时加载频道
+    ///
+    /// 远程订阅先加载本地副本，再尝试从上游更新；上游失败时继续使用本地副本
+    pub async fn load_initial(&self, channel_manager: &ChannelManager) -> Result<usize> {
+        channel_manager.register_source(&self.name, self.location());
+
+        let url = match &self.location {
+            SourceLocation::File(_) => return self.reload(channel_manager).await,
+            SourceLocation::Remote(url) => url,
+        };
+
+        let cached = match self.load_cached(channel_manager) {
+            Ok(count) => {
+                info!("Loaded {} cached channels for source {}", count, self.name);
+                Some(count)
+            }
+            Err(e) => {
+                warn!("No usable cached copy for source {}: {}", self.name, e);
+                None
+            }
+        };
+
+        let result = self.refresh(channel_manager).await;
+        if let Err(e) = &result {
+            channel_manager.record_source_error(&self.name, &e.to_string());
+        }
+
+        match result {
+            Ok(count) => Ok(count),
+            Err(e) => match cached {
+                Some(count) => {
+                    warn!(
+                        "Failed to fetch {}: {}, using cached copy from {}",
+                        url,
+                        e,
+                        self.cache_path.display()
+                    );
+                    Ok(count)
+                }
+                None => Err(e),
+            },
+        }
+    }
+
+    /// 重新加载频道（本地文件重新读取，远程订阅发起条件请求）
+    ///
+    /// 失败时记录到来源状态中，并保留该来源原有频道
+    pub async fn reload(&self, channel_manager: &ChannelManager) -> Result<usize> {
+        let result = match &self.location {
+            SourceLocation::File(path) => M3uParser::parse_file(path)
+                .and_then(|playlist| self.apply_parsed(channel_manager, playlist)),
+            SourceLocation::Remote(_) => self.refresh(channel_manager).await,
+        };
+
+        if let Err(e) = &result {
+            channel_manager.record_source_error(&self.name, &e.to_string());
+        }
+
+        result
+    }
+
+    /// 解析 M3U 内容并写入频道管理器
+    fn apply_content(&self, channel_manager: &ChannelManager, content: &str) -> Result<usize> {
+        self.apply_parsed(channel_manager, M3uParser::parse(content))
+    }
+
+    /// 记录解析报告，标记来源并应用分组前缀后写入频道管理器
+    ///
+    /// 没有解析出任何频道时返回错误，保留该来源原有频道
+    fn apply_parsed(
+        &self,
+        channel_manager: &ChannelManager,
+        playlist: ParsedPlaylist,
+    ) -> Result<usize> {
+        let ParsedPlaylist {
+            header,
+            mut channels,
+            issues,
+        } = playlist;
+
+        let skipped = issues.iter().filter(|i| i.skipped).count();
+        for issue in &issues {
+            warn!(
+                "{}:{} [{:?}] {}{}",
+                self.location(),
+                issue.line,
+                issue.kind,
+                issue.message,
+                if issue.skipped { " (skipped)" } else { "" }
+            );
+        }
+        if !issues.is_empty() {
+            warn!(
+                "Source {} parsed with {} issues, {} entries skipped",
+                self.name,
+                issues.len(),
+                skipped
+            );
+        }
+
+        channel_manager.record_parse_report(ParseReport {
+            source: self.name.clone(),
+            parsed_at: unix_now(),
+            channel_count: channels.len(),
+            skipped_count: skipped,
+            issues,
+        });
+
+        if channels.is_empty() {
+            return Err(AppError::InvalidM3U(format!(
+                "No valid channels found in source {}",
+                self.name
+            )));
+        }
+
+        channel_manager.set_source_header(&self.name, header);
+        M3uParser::assign_ids(&mut channels, &self.name);
+
+        for channel in &mut channels {
+            channel.source = self.name.clone();
+            if let Some(prefix) = &self.group_prefix {
+                channel.group = format!("{}{}", prefix, channel.group);
+            }
+        }
+
+        channel_manager.update_source(&self.name, channels)
+    }
+
+    /// 从上游拉取远程订阅
+    ///
+    /// 内容未变化时直接返回当前频道数；解析失败时保留原有频道和本地副本
+    async fn refresh(&self, channel_manager: &ChannelManager) -> Result<usize> {
+        let SourceLocation::Remote(url) = &self.location else {
+            return Err(AppError::Internal(format!(
+                "Source {} is not a remote subscription",
+                self.name
+            )));
+        };
+
+        let validators = self.validators.lock().clone();
+        let outcome = self
+            .proxy
+            .fetch_conditional(
+                url,
+                validators.etag.as_deref(),
+                validators.last_modified.as_deref(),
+            )
+            .await?;
+
+        match outcome {
+            FetchOutcome::NotModified => {
+                info!("Source {} not modified since last fetch", self.name);
+                Ok(channel_manager.get_source_channel_count(&self.name))
+            }
+            FetchOutcome::Modified {
+                body,
+                etag,
+                last_modified,
+            } => {
+                let count = self.apply_content(channel_manager, &body)?;
+
+                let validators = Validators {
+                    etag,
+                    last_modified,
+                };
+                if let Err(e) = self.save_cache(&body, &validators) {
+                    warn!("Failed to save cached copy of source {}: {}", self.name, e);
+                }
+                *self.validators.lock() = validators;
+
+                Ok(count)
+            }
+        }
+    }
+
+    /// 从本地副本加载频道，并恢复条件请求校验信息
+    fn load_cached(&self, channel_manager: &ChannelManager) -> Result<usize> {
+        let content = fs::read_to_string(&self.cache_path)?;
+        let count = self.apply_content(channel_manager, &content)?;
+
+        if let Ok(meta) = fs::read_to_string(&self.meta_path)
+            && let Ok(validators) = serde_json::from_str::<Validators>(&meta)
+        {
+            *self.validators.lock() = validators;
+        }
+
+        Ok(count)
+    }
+
+    /// 保存本地副本（先写临时文件再重命名，避免留下半截文件）
+    fn save_cache(&self, content: &str, validators: &Validators) -> Result<()> {
+        if let Some(dir) = self.cache_path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let tmp_path = self.cache_path.with_extension("m3u.tmp");
+        fs::write(&tmp_path, content)?;
+        fs::rename(&tmp_path, &self.cache_path)?;
+
+        let meta = serde_json::to_string(validators)
+            .map_err(|e| AppError::Internal(format!("Failed to encode source metadata: {}", e)))?;
+        fs::write(&self.meta_path, meta)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_location() {
+        assert_eq!(
+            SourceLocation::parse("./Gather.m3u"),
+            SourceLocation::File("./Gather.m3u".to_string())
+        );
+        assert_eq!(
+            SourceLocation::parse("HTTPS://example.com/list.m3u"),
+            SourceLocation::Remote("HTTPS://example.com/list.m3u".to_string())
+        );
+    }
+}
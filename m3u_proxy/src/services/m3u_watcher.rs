@@ -0,0 +1,87 @@
+use std::fs;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info};
+
+use crate::services::{ChannelManager, M3uSource};
+
+/// 文件签名（修改时间 + 大小），用于判断文件是否变化
+type FileSignature = (Option<SystemTime>, u64);
+
+fn file_signature(path: &str) -> Option<FileSignature> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok(), metadata.len()))
+}
+
+/// 启动 M3U 文件监听任务
+///
+/// 按固定间隔检查文件的修改时间和大小，变化时重新加载频道列表。
+/// 解析失败时保留原有频道列表并记录错误。
+pub fn spawn_m3u_watcher(
+    channel_manager: Arc<ChannelManager>,
+    source: Arc<M3uSource>,
+    interval: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let path = source.location().to_string();
+        info!("Watching {} for changes every {:?}", path, interval);
+
+        let mut last_signature = file_signature(&path);
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+
+            let signature = file_signature(&path);
+            if signature == last_signature {
+                continue;
+            }
+            last_signature = signature;
+
+            if signature.is_none() {
+                debug!("M3U file {} is missing, keeping current channel list", path);
+                continue;
+            }
+
+            info!("Detected change in {}, reloading channels", path);
+            match source.reload(&channel_manager).await {
+                Ok(count) => info!("Hot-reloaded {} channels from {}", count, path),
+                Err(e) => error!(
+                    "Failed to reload {}: {}, keeping previous channel list",
+                    path, e
+                ),
+            }
+        }
+    })
+}
+
+/// 启动远程订阅定时刷新任务
+///
+/// 每次刷新都会发起条件请求，上游未变化时不会重新下载；失败时保留原有频道列表
+pub fn spawn_m3u_refresher(
+    channel_manager: Arc<ChannelManager>,
+    source: Arc<M3uSource>,
+    interval: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        info!("Refreshing {} every {:?}", source.location(), interval);
+
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+
+            match source.reload(&channel_manager).await {
+                Ok(count) => debug!("Refreshed {}: {} channels", source.location(), count),
+                Err(e) => error!(
+                    "Failed to refresh {}: {}, keeping previous channel list",
+                    source.location(),
+                    e
+                ),
+            }
+        }
+    })
+}
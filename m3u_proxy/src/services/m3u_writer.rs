@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::models::Channel;
+
+/// M3U 播放列表生成器
+///
+/// 把频道目录重新输出为标准 M3U，供 VLC、Kodi、TiviMate 等播放器使用
+pub struct M3uWriter;
+
+impl M3uWriter {
+    /// 生成 M3U 内容，每个频道的地址指向本代理的 `/api/play/{id}/stream`，
+    /// 指定 `token` 时附加 `?token=`，供无法设置请求头的播放器认证
+    pub fn write(
+        header: &BTreeMap<String, String>,
+        channels: &[Channel],
+        base_url: &str,
+        token: Option<&str>,
+    ) -> String {
+        let mut output = String::from("#EXTM3U");
+        for (key, value) in header {
+            write_attribute(&mut output, key, value);
+        }
+        output.push('\n');
+
+        for channel in channels {
+            output.push_str("#EXTINF:-1");
+            write_attribute(&mut output, "tvg-id", &channel.tvg_id);
+            if let Some(tvg_name) = &channel.tvg_name {
+                write_attribute(&mut output, "tvg-name", tvg_name);
+            }
+            if let Some(logo) = &channel.logo {
+                write_attribute(&mut output, "tvg-logo", logo);
+            }
+            write_attribute(&mut output, "group-title", &channel.group);
+            for (key, value) in &channel.attributes {
+                write_attribute(&mut output, key, value);
+            }
+            output.push(',');
+            output.push_str(&single_line(&channel.name));
+            output.push('\n');
+
+            for (key, value) in &channel.kodi_props {
+                let _ = writeln!(output, "#KODIPROP:{}={}", key, single_line(value));
+            }
+            for (key, value) in &channel.vlc_options {
+                let _ = writeln!(output, "#EXTVLCOPT:{}={}", key, single_line(value));
+            }
+
+            let _ = write!(
+                output,
+                "{}/api/play/{}/stream",
+                base_url.trim_end_matches('/'),
+                urlencoding::encode(&channel.id)
+            );
+            if let Some(token) = token {
+                let _ = write!(output, "?token={}", urlencoding::encode(token));
+            }
+            output.push('\n');
+        }
+
+        output
+    }
+}
+
+/// 写入 ` key="value"`（M3U 没有转义规则，双引号替换为单引号）
+fn write_attribute(output: &mut String, key: &str, value: &str) {
+    let _ = write!(output, " {}=\"{}\"", key, single_line(value).replace('"', "'"));
+}
+
+/// 去掉换行，避免破坏行结构
+fn single_line(value: &str) -> String {
+    value.replace(['\r', '\n'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::StreamType;
+
+    #[test]
+    fn test_write_playlist() {
+        let mut header = BTreeMap::new();
+        header.insert(
+            "x-tvg-url".to_string(),
+            "https://epg.example.com/epg.xml".to_string(),
+        );
+
+        let mut attributes = BTreeMap::new();
+        attributes.insert("tvg-chno".to_string(), "1".to_string());
+
+        let channels = vec![Channel {
+            id: "ch_1".to_string(),
+            tvg_id: "CCTV1".to_string(),
+            tvg_name: Some("CCTV1".to_string()),
+            name: "CCTV-1 \"综合\"".to_string(),
+            logo: None,
+            group: "央视".to_string(),
+            url: "http://example.com/cctv1.m3u8".to_string(),
+            stream_type: StreamType::HLS,
+            attributes,
+            ..Default::default()
+        }];
+
+        let output = M3uWriter::write(&header, &channels, "http://proxy.local:8006/", None);
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert_eq!(lines[0], r#"#EXTM3U x-tvg-url="https://epg.example.com/epg.xml""#);
+        assert_eq!(
+            lines[1],
+            r#"#EXTINF:-1 tvg-id="CCTV1" tvg-name="CCTV1" group-title="央视" tvg-chno="1",CCTV-1 "综合""#
+        );
+        assert_eq!(lines[2], "http://proxy.local:8006/api/play/ch_1/stream");
+
+        let output = M3uWriter::write(&header, &channels, "http://proxy.local:8006", Some("k+1"));
+        assert!(output.ends_with("/api/play/ch_1/stream?token=k%2B1\n"));
+    }
+}
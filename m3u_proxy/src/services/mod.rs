@@ -1,9 +1,35 @@
 pub mod m3u_parser;
 pub mod channel_manager;
 pub mod proxy;
+pub mod proxy_cache;
+pub mod disk_cache;
+pub mod segment_flight;
 pub mod m3u8_rewriter;
+pub mod m3u_source;
+pub mod m3u_watcher;
+pub mod m3u_writer;
+pub mod xmltv;
+pub mod xmltv_writer;
+pub mod epg;
+pub mod catchup;
+pub mod health_checker;
+pub mod url_policy;
+pub mod proxy_token;
+pub mod auth;
 
 pub use m3u_parser::M3uParser;
 pub use channel_manager::ChannelManager;
 pub use proxy::ProxyService;
+pub use proxy_cache::ProxyCache;
+pub use disk_cache::DiskCache;
 pub use m3u8_rewriter::M3u8Rewriter;
+pub use m3u_source::M3uSource;
+pub use m3u_watcher::{spawn_m3u_refresher, spawn_m3u_watcher};
+pub use m3u_writer::M3uWriter;
+pub use epg::{spawn_epg_refresher, EpgService};
+pub use xmltv_writer::XmltvWriter;
+pub use catchup::CatchupResolver;
+pub use health_checker::{spawn_health_checker, HealthChecker};
+pub use url_policy::UrlPolicy;
+pub use proxy_token::ProxyTokens;
+pub use auth::Authenticator;
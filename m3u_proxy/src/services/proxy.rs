@@ -1,138 +1,443 @@
 use axum::{
-    body::Body,
-    http::{HeaderMap, HeaderValue},
+    body::{Body, Bytes},
+    http::HeaderValue,
     response::Response,
 };
-use reqwest::Client;
+use reqwest::{header, redirect, Client, RequestBuilder, StatusCode};
+use std::sync::Arc;
 use std::time::Duration;
-use tracing::info;
+use tracing::{info, warn};
+use url::Url;
 
-use crate::error::AppError;
+use crate::error::{origin_host, AppError};
+use crate::models::Channel;
+use crate::services::proxy_cache::{CacheStats, ProxyCache};
+use crate::services::segment_flight::SegmentFlights;
+use crate::services::url_policy::{PolicyResolver, PolicyViolation, UrlPolicy};
+
+/// 把 reqwest 错误转换为 AppError，超时单独区分（504），被地址策略拒绝的返回 403
+///
+/// 错误信息会返回给客户端，上游地址只记录在日志中
+fn upstream_error(context: &str, e: reqwest::Error) -> AppError {
+    let mut source = std::error::Error::source(&e);
+    while let Some(inner) = source {
+        if let Some(violation) = inner.downcast_ref::<PolicyViolation>() {
+            return AppError::Forbidden(violation.to_string());
+        }
+        source = inner.source();
+    }
+
+    warn!("{}: {}", context, e);
+    let host = e.url().map(|url| origin_host(url.as_str()));
+    let timeout = e.is_timeout();
+    let e = e.without_url();
+    if timeout {
+        AppError::UpstreamTimeout {
+            host: host.unwrap_or_default(),
+            message: format!("{}: {}", context, e),
+        }
+    } else {
+        match host {
+            Some(host) => AppError::ProxyError(format!("{} from {}: {}", context, host, e)),
+            None => AppError::ProxyError(format!("{}: {}", context, e)),
+        }
+    }
+}
+
+/// 构建视频片段响应，`cache_status` 写入 `X-Cache` 头（`HIT` / `MISS`）
+fn stream_response(
+    content_type: Option<String>,
+    content_length: Option<u64>,
+    body: Body,
+    cache_status: &'static str,
+) -> Response {
+    let mut response = Response::new(body);
+    let headers = response.headers_mut();
+    if let Some(content_type) = content_type.and_then(|v| HeaderValue::from_str(&v).ok()) {
+        headers.insert(header::CONTENT_TYPE, content_type);
+    }
+    if let Some(length) = content_length {
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from(length));
+    }
+    headers.insert("x-cache", HeaderValue::from_static(cache_status));
+    headers.insert(
+        "access-control-allow-origin",
+        HeaderValue::from_static("*"),
+    );
+    response
+}
+
+/// 请求上游时附加的频道级选项（来自 `#EXTVLCOPT`）
+#[derive(Debug, Clone, Default)]
+pub struct UpstreamOptions {
+    pub user_agent: Option<String>,
+    pub referrer: Option<String>,
+}
+
+impl UpstreamOptions {
+    /// 从频道配置中提取 User-Agent 和 Referer
+    pub fn from_channel(channel: &Channel) -> Self {
+        Self {
+            user_agent: channel.user_agent().map(|v| v.to_string()),
+            referrer: channel.referrer().map(|v| v.to_string()),
+        }
+    }
+
+    /// 缓存和合并请求使用的键：上游可能按 User-Agent / Referer 返回不同内容，设置了这些选项时键中包含它们
+    pub fn cache_key(&self, url: &str) -> String {
+        if self.user_agent.is_none() && self.referrer.is_none() {
+            return url.to_string();
+        }
+        format!("{} {}", url, serde_json::json!([self.user_agent, self.referrer]))
+    }
+}
+
+/// 条件请求结果
+pub enum FetchOutcome {
+    /// 上游内容未变化（304）
+    NotModified,
+    /// 上游返回了新内容
+    Modified {
+        body: String,
+        etag: Option<String>,
+        last_modified: Option<String>,
+    },
+}
+
+/// 按频道选项构建 GET 请求
+fn build_request(client: &Client, url: &str, options: &UpstreamOptions) -> RequestBuilder {
+    let mut request = client.get(url);
+    if let Some(user_agent) = &options.user_agent {
+        request = request.header(header::USER_AGENT, user_agent);
+    }
+    if let Some(referrer) = &options.referrer {
+        request = request.header(header::REFERER, referrer);
+    }
+    request
+}
 
 /// HTTP 代理服务
 pub struct ProxyService {
+    /// 内部请求（订阅、EPG、健康检查）使用的客户端，启用地址策略时检查 DNS 解析结果和重定向
     client: Client,
+    /// 代理接口使用的客户端，启用地址策略时同上，重定向还要检查端口
+    proxy_client: Client,
+    timeout: Duration,
+    policy: Option<Arc<UrlPolicy>>,
+    cache: Option<ProxyCache>,
+    flights: SegmentFlights,
 }
 
 impl ProxyService {
     /// 创建新的代理服务实例
     pub fn new(timeout: u64) -> Result<Self, AppError> {
+        let timeout = Duration::from_secs(timeout);
         let client = Client::builder()
-            .timeout(Duration::from_secs(timeout))
+            .timeout(timeout)
             .build()
             .map_err(|e| AppError::ProxyError(format!("Failed to create HTTP client: {}", e)))?;
 
-        Ok(Self { client })
+        Ok(Self {
+            proxy_client: client.clone(),
+            client,
+            timeout,
+            policy: None,
+            cache: None,
+            flights: SegmentFlights::default(),
+        })
     }
 
-    /// 代理 GET 请求
-    pub async fn proxy_get(&self, url: &str) -> Result<Response, AppError> {
-        info!("Proxying GET request to: {}", url);
+    /// 启用 SSRF 防护：代理接口检查目标地址白名单，内部请求检查解析后的 IP
+    pub fn with_url_policy(mut self, policy: Arc<UrlPolicy>) -> Result<Self, AppError> {
+        self.proxy_client = policed_client(self.timeout, &policy, UrlPolicy::check_redirect)?;
+        self.client = policed_client(self.timeout, &policy, UrlPolicy::check_fetch)?;
+        self.policy = Some(policy);
+        Ok(self)
+    }
 
-        // 发送请求
-        let response = self
-            .client
-            .get(url)
-            .send()
-            .await
-            .map_err(|e| AppError::ProxyError(format!("Failed to fetch URL: {}", e)))?;
+    /// 启用播放列表和视频片段缓存
+    pub fn with_cache(mut self, cache: ProxyCache) -> Self {
+        self.flights = SegmentFlights::new(Some(cache.clone()));
+        self.cache = Some(cache);
+        self
+    }
 
-        // 获取状态码
-        let status = response.status();
+    /// 构建带频道选项的上游 GET 请求
+    fn upstream_get(&self, url: &str, options: &UpstreamOptions) -> RequestBuilder {
+        build_request(&self.client, url, options)
+    }
 
-        // 构建响应头
-        let mut headers = HeaderMap::new();
-
-        // 复制重要的响应头
-        for (key, value) in response.headers() {
-            let key_str = key.as_str();
-            // 跳过某些不应该转发的头
-            if key_str == "transfer-encoding"
-                || key_str == "connection"
-                || key_str == "keep-alive" {
-                continue;
-            }
+    /// 检查内部请求的目标地址（IP 字面量不经过 `PolicyResolver`）
+    fn check_fetch(&self, url: &str) -> Result<(), AppError> {
+        let Some(policy) = &self.policy else {
+            return Ok(());
+        };
+        let parsed = Url::parse(url)
+            .map_err(|e| AppError::ProxyError(format!("Invalid upstream URL: {}", e)))?;
+        policy
+            .check_fetch(&parsed)
+            .map_err(|e| AppError::Forbidden(e.to_string()))
+    }
+
+    /// 检查代理接口的目标地址
+    fn check_target(&self, url: &str) -> Result<(), AppError> {
+        match &self.policy {
+            Some(policy) => policy.check(url),
+            None => Ok(()),
+        }
+    }
+
+    /// 记录播放列表中引用的地址，之后允许代理这些主机
+    pub async fn allow_referenced(&self, urls: Vec<String>) {
+        if let Some(policy) = &self.policy {
+            policy.learn(urls).await;
+        }
+    }
+
+    /// 片段缓存的命中统计，未启用缓存时返回 None
+    pub async fn cache_stats(&self) -> Option<CacheStats> {
+        match &self.cache {
+            Some(cache) => Some(cache.stats().await),
+            None => None,
+        }
+    }
 
-            if let Ok(value) = HeaderValue::from_bytes(value.as_bytes()) {
-                headers.insert(key.clone(), value);
+    /// 获取 M3U8 播放列表并校验 `#EXTM3U` 头，启用缓存时同一地址的并发请求只请求一次上游
+    pub async fn fetch_playlist(
+        &self,
+        url: &str,
+        options: &UpstreamOptions,
+    ) -> Result<String, AppError> {
+        self.check_target(url)?;
+        match &self.cache {
+            Some(cache) => {
+                let key = options.cache_key(url);
+                cache.playlist(&key, self.load_playlist(url, options)).await
             }
+            None => self.load_playlist(url, options).await,
         }
+    }
 
-        // 添加 CORS 头
-        headers.insert(
-            "access-control-allow-origin",
-            HeaderValue::from_static("*"),
-        );
-        headers.insert(
-            "access-control-allow-methods",
-            HeaderValue::from_static("GET, POST, OPTIONS"),
-        );
-        headers.insert(
-            "access-control-allow-headers",
-            HeaderValue::from_static("*"),
-        );
-
-        // 获取响应体
+    async fn load_playlist(
+        &self,
+        url: &str,
+        options: &UpstreamOptions,
+    ) -> Result<String, AppError> {
+        info!("Fetching playlist: {}", url);
+
+        let request = build_request(&self.proxy_client, url, options);
+        let response = send_request(request, url).await?;
         let bytes = response
             .bytes()
             .await
-            .map_err(|e| AppError::ProxyError(format!("Failed to read response body: {}", e)))?;
+            .map_err(|e| upstream_error("Failed to read response body", e))?;
+
+        let content = String::from_utf8(bytes.to_vec()).map_err(|e| {
+            AppError::upstream_invalid(url, format!("Invalid UTF-8 in playlist: {}", e))
+        })?;
 
-        // 构建响应
-        let mut response = Response::new(Body::from(bytes));
-        *response.status_mut() = status;
-        *response.headers_mut() = headers;
+        // 检查是否是 M3U8 内容
+        if !content
+            .trim_start_matches('\u{feff}')
+            .trim_start()
+            .starts_with("#EXTM3U")
+        {
+            return Err(AppError::upstream_invalid(
+                url,
+                "Response is not a valid M3U8 playlist",
+            ));
+        }
 
-        Ok(response)
+        Ok(content)
     }
 
     /// 代理流式请求（用于视频片段）
-    pub async fn proxy_stream(&self, url: &str) -> Result<Response, AppError> {
+    pub async fn proxy_stream(
+        &self,
+        url: &str,
+        options: &UpstreamOptions,
+    ) -> Result<Response, AppError> {
+        self.check_target(url)?;
+        let key = options.cache_key(url);
+        if let Some(cache) = &self.cache
+            && let Some(segment) = cache.get_segment(&key).await
+        {
+            let length = segment.body.len() as u64;
+            return Ok(stream_response(
+                segment.content_type,
+                Some(length),
+                Body::from(segment.body),
+                "HIT",
+            ));
+        }
+
         info!("Proxying stream request to: {}", url);
 
-        // 发送请求
+        // 同一片段的并发请求共用一个上游请求，后到的请求随上游数据同步接收
+        let request = build_request(&self.proxy_client, url, options);
+        let target = url.to_string();
+        let (head, body) = self
+            .flights
+            .fetch(&key, async move { send_request(request, &target).await })
+            .await?;
+
+        Ok(stream_response(
+            head.content_type,
+            head.content_length,
+            body,
+            "MISS",
+        ))
+    }
+
+    /// 发起带 ETag / Last-Modified 的条件 GET 请求（用于远程 M3U 订阅）
+    pub async fn fetch_conditional(
+        &self,
+        url: &str,
+        etag: Option<&str>,
+        last_modified: Option<&str>,
+    ) -> Result<FetchOutcome, AppError> {
+        info!("Fetching remote playlist: {}", url);
+        self.check_fetch(url)?;
+
+        let mut request = self.client.get(url);
+        if let Some(etag) = etag {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = last_modified {
+            request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| upstream_error("Failed to fetch URL", e))?;
+
+        let status = response.status();
+        if status == StatusCode::NOT_MODIFIED {
+            return Ok(FetchOutcome::NotModified);
+        }
+        if !status.is_success() {
+            return Err(AppError::upstream_status(status.as_u16(), url));
+        }
+
+        let header_value = |name: header::HeaderName| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string())
+        };
+        let etag = header_value(header::ETAG);
+        let last_modified = header_value(header::LAST_MODIFIED);
+
+        let body = response
+            .text()
+            .await
+            .map_err(|e| upstream_error("Failed to read response body", e))?;
+
+        Ok(FetchOutcome::Modified {
+            body,
+            etag,
+            last_modified,
+        })
+    }
+
+    /// 下载完整的响应体（用于 EPG 等非流媒体资源）
+    pub async fn fetch_bytes(&self, url: &str) -> Result<Bytes, AppError> {
+        info!("Fetching remote resource: {}", url);
+        self.check_fetch(url)?;
+
         let response = self
             .client
             .get(url)
             .send()
             .await
-            .map_err(|e| AppError::ProxyError(format!("Failed to fetch stream: {}", e)))?;
+            .map_err(|e| upstream_error("Failed to fetch URL", e))?;
 
-        // 获取状态码
         let status = response.status();
+        if !status.is_success() {
+            return Err(AppError::upstream_status(status.as_u16(), url));
+        }
 
-        // 构建响应头
-        let mut headers = HeaderMap::new();
+        response
+            .bytes()
+            .await
+            .map_err(|e| upstream_error("Failed to read response body", e))
+    }
 
-        // 复制响应头
-        for (key, value) in response.headers() {
-            let key_str = key.as_str();
-            if key_str == "transfer-encoding"
-                || key_str == "connection"
-                || key_str == "keep-alive" {
-                continue;
-            }
+    /// 获取文本内容（用于健康检查读取 HLS 播放列表），非 2xx 状态返回错误
+    pub async fn fetch_text(&self, url: &str, options: &UpstreamOptions) -> Result<String, AppError> {
+        let response = self.send_checked(url, options).await?;
 
-            if let Ok(value) = HeaderValue::from_bytes(value.as_bytes()) {
-                headers.insert(key.clone(), value);
-            }
+        response
+            .text()
+            .await
+            .map_err(|e| upstream_error("Failed to read response body", e))
+    }
+
+    /// 只读取响应的第一个数据块，确认上游确实在返回数据（用于健康检查）
+    pub async fn fetch_first_chunk(
+        &self,
+        url: &str,
+        options: &UpstreamOptions,
+    ) -> Result<usize, AppError> {
+        let mut response = self.send_checked(url, options).await?;
+
+        match response.chunk().await {
+            Ok(Some(chunk)) => Ok(chunk.len()),
+            Ok(None) => Err(AppError::ProxyError(format!(
+                "Empty response from {}",
+                origin_host(url)
+            ))),
+            Err(e) => Err(upstream_error("Failed to read response body", e)),
         }
+    }
 
-        // 添加 CORS 头
-        headers.insert(
-            "access-control-allow-origin",
-            HeaderValue::from_static("*"),
-        );
+    /// 发送请求，非 2xx 状态返回错误
+    async fn send_checked(
+        &self,
+        url: &str,
+        options: &UpstreamOptions,
+    ) -> Result<reqwest::Response, AppError> {
+        self.check_fetch(url)?;
+        send_request(self.upstream_get(url, options), url).await
+    }
+}
 
-        // 将 reqwest 的流转换为 axum 的 Body
-        let stream = response.bytes_stream();
-        let body = Body::from_stream(stream);
+/// 启用地址策略的客户端：DNS 解析结果由 `PolicyResolver` 检查，每次重定向由 `check_redirect` 检查
+fn policed_client(
+    timeout: Duration,
+    policy: &Arc<UrlPolicy>,
+    check_redirect: fn(&UrlPolicy, &Url) -> Result<(), PolicyViolation>,
+) -> Result<Client, AppError> {
+    let redirect_policy = policy.clone();
+    Client::builder()
+        .timeout(timeout)
+        .dns_resolver(Arc::new(PolicyResolver::new(policy.clone())))
+        .redirect(redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= 10 {
+                return attempt.error("too many redirects");
+            }
+            match check_redirect(&redirect_policy, attempt.url()) {
+                Ok(()) => attempt.follow(),
+                Err(violation) => attempt.error(violation),
+            }
+        }))
+        .build()
+        .map_err(|e| AppError::ProxyError(format!("Failed to create HTTP client: {}", e)))
+}
 
-        // 构建响应
-        let mut response = Response::new(body);
-        *response.status_mut() = status;
-        *response.headers_mut() = headers;
+/// 发送请求，非 2xx 状态返回错误
+async fn send_request(request: RequestBuilder, url: &str) -> Result<reqwest::Response, AppError> {
+    let response = request
+        .send()
+        .await
+        .map_err(|e| upstream_error("Failed to fetch URL", e))?;
 
-        Ok(response)
+    let status = response.status();
+    if !status.is_success() {
+        return Err(AppError::upstream_status(status.as_u16(), url));
     }
+
+    Ok(response)
 }
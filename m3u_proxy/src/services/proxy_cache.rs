@@ -0,0 +1,284 @@
+use axum::body::Bytes;
+use moka::Expiry;
+use moka::future::Cache;
+use serde::Serialize;
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+use crate::error::{AppError, Result};
+use crate::services::disk_cache::{DiskCache, DiskWriter};
+
+/// 缓存的视频片段
+#[derive(Clone)]
+pub struct CachedSegment {
+    pub content_type: Option<String>,
+    pub body: Bytes,
+}
+
+/// 缓存的播放列表，每条记录有自己的过期时间
+#[derive(Clone)]
+struct CachedPlaylist {
+    content: String,
+    ttl: Duration,
+}
+
+struct PlaylistExpiry;
+
+impl Expiry<String, CachedPlaylist> for PlaylistExpiry {
+    fn expire_after_create(
+        &self,
+        _key: &String,
+        value: &CachedPlaylist,
+        _created_at: Instant,
+    ) -> Option<Duration> {
+        Some(value.ttl)
+    }
+}
+
+/// 单个缓存层的命中计数
+#[derive(Default)]
+struct TierCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl TierCounters {
+    fn record(&self, hit: bool) {
+        let counter = if hit { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn stats(&self, entries: u64, bytes: u64, capacity: u64) -> TierStats {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let total = hits + misses;
+        TierStats {
+            hits,
+            misses,
+            hit_rate: if total == 0 {
+                0.0
+            } else {
+                hits as f64 / total as f64
+            },
+            entries,
+            bytes,
+            capacity,
+        }
+    }
+}
+
+/// 缓存层统计
+#[derive(Debug, Serialize)]
+pub struct TierStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_rate: f64,
+    pub entries: u64,
+    pub bytes: u64,
+    pub capacity: u64,
+}
+
+/// 片段缓存统计（内存层和可选的磁盘层）
+#[derive(Debug, Serialize)]
+pub struct CacheStats {
+    pub memory: TierStats,
+    pub disk: Option<TierStats>,
+}
+
+/// 代理缓存
+///
+/// 播放列表和视频片段按上游 URL 缓存（频道设置了请求头时键中还包含请求头，见 `UpstreamOptions::cache_key`）；同一播放列表的并发请求会合并为一次上游请求，
+/// 片段的并发请求由 `SegmentFlights` 合并；内存未命中时再查找可选的磁盘缓存
+#[derive(Clone)]
+pub struct ProxyCache {
+    playlists: Cache<String, CachedPlaylist>,
+    segments: Cache<String, CachedSegment>,
+    playlist_ttl: Duration,
+    segment_capacity: u64,
+    disk: Option<Arc<DiskCache>>,
+    memory_counters: Arc<TierCounters>,
+    disk_counters: Arc<TierCounters>,
+}
+
+impl ProxyCache {
+    /// 创建缓存，`segment_capacity` 为片段缓存的总字节数
+    pub fn new(playlist_ttl: Duration, segment_ttl: Duration, segment_capacity: u64) -> Self {
+        let playlists = Cache::builder()
+            .max_capacity(1024)
+            .expire_after(PlaylistExpiry)
+            .build();
+        let segments = Cache::builder()
+            .max_capacity(segment_capacity)
+            .weigher(|_key: &String, segment: &CachedSegment| {
+                u32::try_from(segment.body.len()).unwrap_or(u32::MAX)
+            })
+            .time_to_live(segment_ttl)
+            .build();
+
+        Self {
+            playlists,
+            segments,
+            playlist_ttl,
+            segment_capacity,
+            disk: None,
+            memory_counters: Arc::default(),
+            disk_counters: Arc::default(),
+        }
+    }
+
+    /// 在内存缓存之后增加磁盘缓存
+    pub fn with_disk(mut self, disk: DiskCache) -> Self {
+        self.disk = Some(Arc::new(disk));
+        self
+    }
+
+    /// 读取播放列表，未命中时通过 `load` 获取并缓存
+    pub async fn playlist<F>(&self, url: &str, load: F) -> Result<String>
+    where
+        F: Future<Output = Result<String>>,
+    {
+        let entry = self
+            .playlists
+            .entry_by_ref(url)
+            .or_try_insert_with(async {
+                let content = load.await?;
+                let ttl = playlist_ttl(&content, self.playlist_ttl);
+                Ok::<_, AppError>(CachedPlaylist { content, ttl })
+            })
+            .await?;
+
+        if !entry.is_fresh() {
+            debug!("Playlist cache hit: {}", url);
+        }
+        Ok(entry.into_value().content)
+    }
+
+    /// 读取缓存的视频片段，磁盘命中时同时放入内存缓存
+    pub async fn get_segment(&self, url: &str) -> Option<CachedSegment> {
+        let segment = self.segments.get(url).await;
+        self.memory_counters.record(segment.is_some());
+        if segment.is_some() {
+            debug!("Segment memory cache hit: {}", url);
+            return segment;
+        }
+
+        let disk = self.disk.as_ref()?;
+        let segment = disk.get(url).await;
+        self.disk_counters.record(segment.is_some());
+        if let Some(segment) = &segment {
+            debug!("Segment disk cache hit: {}", url);
+            self.segments.insert(url.to_string(), segment.clone()).await;
+        }
+        segment
+    }
+
+    /// 缓存下载完成的视频片段（内存层）
+    pub async fn insert_segment(&self, url: String, segment: CachedSegment) {
+        self.segments.insert(url, segment).await;
+    }
+
+    /// 内存缓存能保存的最大片段（超过总容量的条目不会被保留）
+    pub fn max_segment_size(&self) -> u64 {
+        self.segment_capacity.min(u64::from(u32::MAX))
+    }
+
+    /// 开始把下载中的片段写入磁盘缓存，未启用磁盘缓存时返回 None
+    pub async fn disk_writer(
+        &self,
+        url: &str,
+        content_type: Option<&str>,
+        content_length: Option<u64>,
+    ) -> Option<DiskWriter> {
+        self.disk
+            .as_ref()?
+            .writer(url, content_type, content_length)
+            .await
+    }
+
+    /// 各缓存层的命中统计
+    pub async fn stats(&self) -> CacheStats {
+        // 先处理 moka 的待办任务，使条目数和容量准确
+        self.segments.run_pending_tasks().await;
+        let memory = self.memory_counters.stats(
+            self.segments.entry_count(),
+            self.segments.weighted_size(),
+            self.segment_capacity,
+        );
+        let disk = self.disk.as_ref().map(|disk| {
+            let (entries, bytes) = disk.usage();
+            self.disk_counters.stats(entries, bytes, disk.capacity())
+        });
+
+        CacheStats { memory, disk }
+    }
+}
+
+/// 播放列表的缓存时间
+///
+/// 直播播放列表最多缓存半个目标时长（`#EXT-X-TARGETDURATION`），避免客户端拿到过期的分片列表；
+/// 点播列表（`#EXT-X-ENDLIST`）和主播放列表使用配置的时间
+fn playlist_ttl(content: &str, max: Duration) -> Duration {
+    if content.contains("#EXT-X-ENDLIST") {
+        return max;
+    }
+
+    content
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("#EXT-X-TARGETDURATION:"))
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .filter(|&seconds| seconds > 0)
+        .map(|seconds| Duration::from_millis(seconds * 500).min(max))
+        .unwrap_or(max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_playlist_ttl() {
+        let max = Duration::from_secs(300);
+        let live = "#EXTM3U\n#EXT-X-TARGETDURATION:6\n#EXTINF:6,\nseg1.ts\n";
+        assert_eq!(playlist_ttl(live, max), Duration::from_secs(3));
+        assert_eq!(
+            playlist_ttl(live, Duration::from_secs(1)),
+            Duration::from_secs(1)
+        );
+
+        let vod = "#EXTM3U\n#EXT-X-TARGETDURATION:6\n#EXTINF:6,\nseg1.ts\n#EXT-X-ENDLIST\n";
+        assert_eq!(playlist_ttl(vod, max), max);
+
+        let master = "#EXTM3U\n#EXT-X-STREAM-INF:BANDWIDTH=800000\nlow.m3u8\n";
+        assert_eq!(playlist_ttl(master, max), max);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_requests_are_collapsed() {
+        let cache = ProxyCache::new(Duration::from_secs(10), Duration::from_secs(10), 1 << 20);
+        let fetches = Arc::new(AtomicUsize::new(0));
+
+        let load = || {
+            let fetches = fetches.clone();
+            async move {
+                fetches.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                Ok("#EXTM3U\n#EXT-X-TARGETDURATION:6\n".to_string())
+            }
+        };
+
+        let url = "http://example.com/live/index.m3u8";
+        let (a, b, c) = tokio::join!(
+            cache.playlist(url, load()),
+            cache.playlist(url, load()),
+            cache.playlist(url, load()),
+        );
+        assert_eq!(a.unwrap(), b.unwrap());
+        assert!(c.is_ok());
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,403 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use ring::aead::{AES_256_GCM, Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::Duration;
+use tracing::info;
+
+use crate::error::{AppError, Result};
+use crate::services::channel_manager::unix_now;
+
+/// 令牌格式版本
+const VERSION: u8 = 1;
+/// 令牌绑定了客户端地址
+const FLAG_BOUND: u8 = 1;
+/// 导出链接令牌，与代理令牌不能互相代替
+const FLAG_LINK: u8 = 2;
+/// 默认的导出链接令牌有效期
+const DEFAULT_LINK_TTL: Duration = Duration::from_secs(7 * 86400);
+/// 版本、标志、过期时间和随机数
+const HEADER_LEN: usize = 2 + 8 + NONCE_LEN;
+/// HMAC-SHA256 签名长度
+const SIGNATURE_LEN: usize = 32;
+
+/// 代理令牌中加密保存的目标
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProxyTarget {
+    /// 上游地址
+    #[serde(rename = "u")]
+    pub url: String,
+    /// 频道 ID，用于沿用频道的上游请求头
+    #[serde(rename = "c", default, skip_serializing_if = "Option::is_none")]
+    pub channel_id: Option<String>,
+    /// 签发时的调用方（API Key 或用户名），未启用认证时为 None
+    #[serde(rename = "s", default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+}
+
+/// 导出链接令牌中记录的调用方
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct LinkClaims {
+    #[serde(rename = "l")]
+    pub subject: String,
+    /// 签发时用户的 `link_version`，与当前配置不一致的令牌已被作废
+    #[serde(rename = "v", default)]
+    pub version: u32,
+}
+
+/// 解密后的令牌
+struct Opened {
+    flags: u8,
+    expires: u64,
+    plaintext: Vec<u8>,
+}
+
+/// 令牌校验失败的原因
+enum OpenError {
+    Invalid,
+    /// 签名无效且令牌绑定了客户端，可能是换了客户端
+    OtherClient,
+}
+
+/// 签发令牌时的上下文
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokenScope<'a> {
+    pub channel_id: Option<&'a str>,
+    pub subject: Option<&'a str>,
+    /// 请求方地址，开启绑定时写入签名
+    pub client: Option<IpAddr>,
+}
+
+/// 代理地址令牌
+///
+/// 代理地址中不再出现上游地址，而是一个不透明的令牌：
+/// `版本 | 标志 | 过期时间 | 随机数 | AES-256-GCM 加密的目标 | HMAC-SHA256 签名`，
+/// 签名覆盖令牌头、密文和（开启绑定时）客户端地址，篡改、过期或换了客户端的令牌都会被拒绝。
+///
+/// 同样格式的导出链接令牌只记录调用方，用于放进导出的播放列表代替用户的密码
+pub struct ProxyTokens {
+    cipher: LessSafeKey,
+    signer: hmac::Key,
+    rng: SystemRandom,
+    ttl: Duration,
+    link_ttl: Duration,
+    bind_client: bool,
+}
+
+impl ProxyTokens {
+    /// 由密钥派生加密和签名密钥，令牌在 `ttl` 后过期，`bind_client` 时令牌只对签发时的客户端地址有效
+    pub fn new(secret: &[u8], ttl: Duration, bind_client: bool) -> Self {
+        let master = hmac::Key::new(hmac::HMAC_SHA256, secret);
+        let derive = |label: &[u8]| hmac::sign(&master, label);
+
+        let cipher_key = derive(b"m3u_proxy proxy token encryption");
+        let cipher = UnboundKey::new(&AES_256_GCM, cipher_key.as_ref())
+            .expect("HMAC-SHA256 output is a valid AES-256 key");
+        let signer_key = derive(b"m3u_proxy proxy token signature");
+
+        Self {
+            cipher: LessSafeKey::new(cipher),
+            signer: hmac::Key::new(hmac::HMAC_SHA256, signer_key.as_ref()),
+            rng: SystemRandom::new(),
+            ttl,
+            link_ttl: DEFAULT_LINK_TTL,
+            bind_client,
+        }
+    }
+
+    /// 设置导出链接令牌的有效期
+    pub fn with_link_ttl(mut self, link_ttl: Duration) -> Self {
+        self.link_ttl = link_ttl;
+        self
+    }
+
+    /// 为上游地址 `url` 签发令牌
+    pub fn issue(&self, url: &str, scope: &TokenScope) -> Result<String> {
+        let target = ProxyTarget {
+            url: url.to_string(),
+            channel_id: scope.channel_id.map(str::to_string),
+            subject: scope.subject.map(str::to_string),
+        };
+        let client = scope.client.filter(|_| self.bind_client);
+        self.seal(&target, 0, unix_now() + self.ttl.as_secs(), client)
+    }
+
+    /// 校验令牌并取出目标，篡改、过期或客户端不符时返回 403
+    pub fn verify(&self, token: &str, client: Option<IpAddr>) -> Result<ProxyTarget> {
+        let invalid = || AppError::Forbidden("Invalid proxy token".to_string());
+
+        let opened = self.open(token, client).map_err(|e| match e {
+            OpenError::Invalid => invalid(),
+            OpenError::OtherClient => AppError::Forbidden(
+                "Invalid proxy token or token issued to another client".to_string(),
+            ),
+        })?;
+        if opened.flags & FLAG_LINK != 0 {
+            return Err(invalid());
+        }
+        if unix_now() > opened.expires {
+            return Err(AppError::Forbidden("Proxy token expired".to_string()));
+        }
+
+        serde_json::from_slice(&opened.plaintext).map_err(|_| invalid())
+    }
+
+    /// 为调用方签发导出链接令牌
+    pub fn issue_link(&self, subject: &str, version: u32) -> Result<String> {
+        let claims = LinkClaims {
+            subject: subject.to_string(),
+            version,
+        };
+        self.seal(&claims, FLAG_LINK, unix_now() + self.link_ttl.as_secs(), None)
+    }
+
+    /// 校验导出链接令牌并取出调用方
+    ///
+    /// 不是有效的链接令牌时返回 None（可能是 API Key），已过期时返回 401
+    pub fn verify_link(&self, token: &str) -> Result<Option<LinkClaims>> {
+        let Ok(opened) = self.open(token, None) else {
+            return Ok(None);
+        };
+        if opened.flags & FLAG_LINK == 0 {
+            return Ok(None);
+        }
+        if unix_now() > opened.expires {
+            return Err(AppError::Unauthorized("Link token expired".to_string()));
+        }
+
+        Ok(serde_json::from_slice(&opened.plaintext).ok())
+    }
+
+    /// 校验签名并解密
+    fn open(&self, token: &str, client: Option<IpAddr>) -> std::result::Result<Opened, OpenError> {
+        let data = URL_SAFE_NO_PAD
+            .decode(token)
+            .map_err(|_| OpenError::Invalid)?;
+        if data.len() < HEADER_LEN + SIGNATURE_LEN || data[0] != VERSION {
+            return Err(OpenError::Invalid);
+        }
+        let (signed, signature) = data.split_at(data.len() - SIGNATURE_LEN);
+
+        let flags = signed[1];
+        let bound = flags & FLAG_BOUND != 0;
+        let client = match (bound, client) {
+            (true, Some(client)) => Some(client),
+            (true, None) => return Err(OpenError::Invalid),
+            (false, _) => None,
+        };
+        hmac::verify(&self.signer, &signing_input(signed, client), signature).map_err(|_| {
+            if bound {
+                OpenError::OtherClient
+            } else {
+                OpenError::Invalid
+            }
+        })?;
+
+        let expires =
+            u64::from_be_bytes(signed[2..10].try_into().map_err(|_| OpenError::Invalid)?);
+        let nonce = Nonce::try_assume_unique_for_key(&signed[10..HEADER_LEN])
+            .map_err(|_| OpenError::Invalid)?;
+        let mut ciphertext = signed[HEADER_LEN..].to_vec();
+        let len = self
+            .cipher
+            .open_in_place(nonce, Aad::from(&signed[..HEADER_LEN]), &mut ciphertext)
+            .map_err(|_| OpenError::Invalid)?
+            .len();
+        ciphertext.truncate(len);
+
+        Ok(Opened {
+            flags,
+            expires,
+            plaintext: ciphertext,
+        })
+    }
+
+    fn seal<T: Serialize>(
+        &self,
+        claims: &T,
+        flags: u8,
+        expires: u64,
+        client: Option<IpAddr>,
+    ) -> Result<String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng
+            .fill(&mut nonce)
+            .map_err(|_| AppError::Internal("Failed to generate token nonce".to_string()))?;
+
+        let mut data = Vec::with_capacity(HEADER_LEN + 128);
+        data.push(VERSION);
+        data.push(if client.is_some() { flags | FLAG_BOUND } else { flags });
+        data.extend_from_slice(&expires.to_be_bytes());
+        data.extend_from_slice(&nonce);
+
+        let mut ciphertext = serde_json::to_vec(claims)
+            .map_err(|e| AppError::Internal(format!("Failed to encode proxy token: {}", e)))?;
+        self.cipher
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(&data[..HEADER_LEN]),
+                &mut ciphertext,
+            )
+            .map_err(|_| AppError::Internal("Failed to encrypt proxy token".to_string()))?;
+        data.extend_from_slice(&ciphertext);
+
+        let signature = hmac::sign(&self.signer, &signing_input(&data, client));
+        data.extend_from_slice(signature.as_ref());
+
+        Ok(URL_SAFE_NO_PAD.encode(data))
+    }
+}
+
+/// 签名内容：令牌头和密文，绑定时追加客户端地址
+fn signing_input(signed: &[u8], client: Option<IpAddr>) -> Vec<u8> {
+    let mut input = signed.to_vec();
+    if let Some(client) = client {
+        input.push(0);
+        input.extend_from_slice(client.to_string().as_bytes());
+    }
+    input
+}
+
+/// 读取令牌密钥文件，不存在时生成随机密钥并保存，重启后已签发的地址仍然有效
+pub fn load_or_create_secret(path: impl AsRef<Path>) -> Result<Vec<u8>> {
+    let path = path.as_ref();
+    if path.exists() {
+        let secret = std::fs::read_to_string(path)?;
+        let secret = secret.trim();
+        if secret.is_empty() {
+            return Err(AppError::Config(format!(
+                "Proxy token key file {} is empty",
+                path.display()
+            )));
+        }
+        return Ok(secret.as_bytes().to_vec());
+    }
+
+    let mut key = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut key)
+        .map_err(|_| AppError::Internal("Failed to generate proxy token key".to_string()))?;
+    let secret: String = key.iter().map(|b| format!("{:02x}", b)).collect();
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, &secret)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    }
+    info!("Generated proxy token key at {}", path.display());
+
+    Ok(secret.into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "http://live.example.com/ch1/index.m3u8";
+
+    fn target() -> ProxyTarget {
+        ProxyTarget {
+            url: URL.to_string(),
+            channel_id: Some("ch_1".to_string()),
+            subject: Some("alice".to_string()),
+        }
+    }
+
+    fn scope(client: Option<IpAddr>) -> TokenScope<'static> {
+        TokenScope {
+            channel_id: Some("ch_1"),
+            subject: Some("alice"),
+            client,
+        }
+    }
+
+    #[test]
+    fn test_token_roundtrip_and_tampering() {
+        let tokens = ProxyTokens::new(b"secret", Duration::from_secs(60), false);
+        let token = tokens.issue(URL, &scope(None)).unwrap();
+
+        assert!(!token.contains("example.com"));
+        assert_eq!(tokens.verify(&token, None).unwrap(), target());
+
+        // 修改任意一个字节都会使签名失效
+        let mut data = URL_SAFE_NO_PAD.decode(&token).unwrap();
+        for i in [1, 5, HEADER_LEN + 1, data.len() - 1] {
+            data[i] ^= 1;
+            assert!(tokens.verify(&URL_SAFE_NO_PAD.encode(&data), None).is_err());
+            data[i] ^= 1;
+        }
+
+        // 其他密钥签发的令牌
+        let other = ProxyTokens::new(b"other", Duration::from_secs(60), false);
+        assert!(other.verify(&token, None).is_err());
+        assert!(tokens.verify("not-a-token", None).is_err());
+
+        // 已过期
+        let expired = tokens.seal(&target(), 0, unix_now() - 1, None).unwrap();
+        assert!(matches!(
+            tokens.verify(&expired, None),
+            Err(AppError::Forbidden(message)) if message.contains("expired")
+        ));
+    }
+
+    #[test]
+    fn test_token_bound_to_client() {
+        let tokens = ProxyTokens::new(b"secret", Duration::from_secs(60), true);
+        let client: IpAddr = "203.0.113.5".parse().unwrap();
+        let token = tokens.issue(URL, &scope(Some(client))).unwrap();
+
+        assert!(tokens.verify(&token, Some(client)).is_ok());
+        assert!(
+            tokens
+                .verify(&token, Some("203.0.113.6".parse().unwrap()))
+                .is_err()
+        );
+        assert!(tokens.verify(&token, None).is_err());
+
+        // 未开启绑定时不记录客户端
+        let unbound = ProxyTokens::new(b"secret", Duration::from_secs(60), false);
+        let token = unbound.issue(URL, &scope(Some(client))).unwrap();
+        assert!(
+            unbound
+                .verify(&token, Some("198.51.100.1".parse().unwrap()))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_link_token() {
+        let tokens = ProxyTokens::new(b"secret", Duration::from_secs(60), true);
+        let link = tokens.issue_link("alice", 2).unwrap();
+        assert_eq!(
+            tokens.verify_link(&link).unwrap(),
+            Some(LinkClaims {
+                subject: "alice".to_string(),
+                version: 2,
+            })
+        );
+
+        // 链接令牌和代理令牌不能互相代替，API Key 不是链接令牌
+        assert!(tokens.verify(&link, None).is_err());
+        let proxy = tokens.issue(URL, &scope(None)).unwrap();
+        assert_eq!(tokens.verify_link(&proxy).unwrap(), None);
+        assert_eq!(tokens.verify_link("k-123").unwrap(), None);
+
+        let claims = LinkClaims {
+            subject: "alice".to_string(),
+            version: 0,
+        };
+        let expired = tokens.seal(&claims, FLAG_LINK, unix_now() - 1, None).unwrap();
+        assert!(matches!(
+            tokens.verify_link(&expired),
+            Err(AppError::Unauthorized(_))
+        ));
+    }
+}
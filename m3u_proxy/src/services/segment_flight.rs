@@ -0,0 +1,520 @@
+use axum::body::{Body, Bytes};
+use futures_util::stream;
+use parking_lot::Mutex;
+use reqwest::header;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::watch;
+use tracing::{debug, warn};
+
+use crate::error::{AppError, Result};
+use crate::services::proxy_cache::{CachedSegment, ProxyCache};
+
+/// 共享下载时最多保留的字节数，超过后之后到达的请求单独下载
+const MAX_SHARED_BYTES: u64 = 32 * 1024 * 1024;
+/// 不再共享后允许领先最慢的读取方的字节数，超过时暂停读取上游
+const BUFFER_WINDOW: usize = 4 * 1024 * 1024;
+
+/// 上游片段的响应头
+#[derive(Debug, Clone)]
+pub struct SegmentHead {
+    pub content_type: Option<String>,
+    pub content_length: Option<u64>,
+}
+
+struct FlightState {
+    /// 上游响应头，请求失败时为错误
+    head: Option<std::result::Result<SegmentHead, Arc<AppError>>>,
+    /// 尚未被所有读取方读完的数据块，`first` 是其中第一块的序号
+    chunks: VecDeque<Bytes>,
+    first: usize,
+    /// 已收到的字节数
+    received: u64,
+    /// `chunks` 中的字节数
+    buffered: usize,
+    /// 是否还接受新的请求加入；共享期间保留全部数据块，供后到的请求从头读取
+    shared: bool,
+    /// 读取方 -> 下一个要读取的块序号
+    readers: HashMap<u64, usize>,
+    next_reader: u64,
+    done: bool,
+    /// 读取响应体时出错
+    error: Option<String>,
+}
+
+impl Default for FlightState {
+    fn default() -> Self {
+        Self {
+            head: None,
+            chunks: VecDeque::new(),
+            first: 0,
+            received: 0,
+            buffered: 0,
+            shared: true,
+            readers: HashMap::new(),
+            next_reader: 0,
+            done: false,
+            error: None,
+        }
+    }
+}
+
+impl FlightState {
+    fn push(&mut self, chunk: Bytes) {
+        self.received += chunk.len() as u64;
+        self.buffered += chunk.len();
+        self.chunks.push_back(chunk);
+    }
+
+    /// 不再共享后丢弃所有读取方都已读过的数据块
+    fn trim(&mut self) {
+        if self.shared {
+            return;
+        }
+        let end = self.first + self.chunks.len();
+        let consumed = self.readers.values().copied().min().unwrap_or(end);
+        while self.first < consumed {
+            let Some(chunk) = self.chunks.pop_front() else {
+                break;
+            };
+            self.buffered -= chunk.len();
+            self.first += 1;
+        }
+    }
+}
+
+/// 一个正在进行的上游请求
+struct Flight {
+    state: Mutex<FlightState>,
+    changed: watch::Sender<()>,
+}
+
+impl Flight {
+    fn new() -> Self {
+        Self {
+            state: Mutex::new(FlightState::default()),
+            changed: watch::Sender::new(()),
+        }
+    }
+
+    /// 修改状态并唤醒所有等待的请求
+    fn update<T>(&self, f: impl FnOnce(&mut FlightState) -> T) -> T {
+        let result = f(&mut self.state.lock());
+        self.changed.send_replace(());
+        result
+    }
+
+    /// 注册一个从头读取的读取方，只能在共享期间调用
+    fn reader(self: &Arc<Self>) -> Reader {
+        let mut state = self.state.lock();
+        let id = state.next_reader;
+        state.next_reader += 1;
+        state.readers.insert(id, 0);
+        Reader {
+            flight: self.clone(),
+            id,
+        }
+    }
+
+    /// 等待上游响应头
+    async fn head(&self) -> Result<SegmentHead> {
+        let mut changed = self.changed.subscribe();
+        loop {
+            changed.borrow_and_update();
+            if let Some(head) = &self.state.lock().head {
+                return head.clone().map_err(AppError::from);
+            }
+            if changed.changed().await.is_err() {
+                return Err(AppError::Internal("Upstream request was dropped".to_string()));
+            }
+        }
+    }
+
+    /// 不再共享时等待读取方跟上，所有读取方都已离开时返回 false
+    async fn wait_for_readers(&self) -> bool {
+        let mut changed = self.changed.subscribe();
+        loop {
+            changed.borrow_and_update();
+            {
+                let state = self.state.lock();
+                if state.shared {
+                    return true;
+                }
+                if state.readers.is_empty() {
+                    return false;
+                }
+                if state.buffered <= BUFFER_WINDOW {
+                    return true;
+                }
+            }
+            if changed.changed().await.is_err() {
+                return false;
+            }
+        }
+    }
+}
+
+/// 读取方，释放（响应结束或客户端断开）时注销
+struct Reader {
+    flight: Arc<Flight>,
+    id: u64,
+}
+
+impl Reader {
+    /// 读取方的响应体：从头读取已收到的数据块，之后跟随上游继续输出
+    fn body(self) -> Body {
+        let changed = self.flight.changed.subscribe();
+        let chunks = stream::unfold(
+            (self, changed, false),
+            |(reader, mut changed, failed)| async move {
+                if failed {
+                    return None;
+                }
+                loop {
+                    changed.borrow_and_update();
+                    {
+                        let mut state = reader.flight.state.lock();
+                        let position = state.readers[&reader.id];
+                        if let Some(chunk) = state.chunks.get(position - state.first).cloned() {
+                            state.readers.insert(reader.id, position + 1);
+                            let shared = state.shared;
+                            if !shared {
+                                state.trim();
+                            }
+                            drop(state);
+                            // 不再共享时下载可能在等待读取方跟上
+                            if !shared {
+                                reader.flight.changed.send_replace(());
+                            }
+                            return Some((Ok(chunk), (reader, changed, false)));
+                        }
+                        if let Some(error) = &state.error {
+                            let error = std::io::Error::other(error.clone());
+                            drop(state);
+                            return Some((Err(error), (reader, changed, true)));
+                        }
+                        if state.done {
+                            return None;
+                        }
+                    }
+                    if changed.changed().await.is_err() {
+                        return None;
+                    }
+                }
+            },
+        );
+        Body::from_stream(chunks)
+    }
+}
+
+impl Drop for Reader {
+    fn drop(&mut self) {
+        self.flight.update(|state| {
+            state.readers.remove(&self.id);
+            state.trim();
+        });
+    }
+}
+
+/// 片段请求合并
+///
+/// 同一 URL 的并发请求共用一个上游请求：第一个请求发起下载，之后的请求从头读取已收到的数据，
+/// 并随上游继续接收，不需要等待完整响应；启用缓存时下载完成后写入片段缓存。
+/// 收到的数据超过共享上限（也不会超过内存缓存能保存的片段大小）后不再接受新的请求加入，
+/// 之后的请求单独下载，已加入的请求读完的数据随即释放
+#[derive(Clone)]
+pub struct SegmentFlights {
+    flights: Arc<Mutex<HashMap<String, Arc<Flight>>>>,
+    cache: Option<ProxyCache>,
+    shared_limit: u64,
+}
+
+impl Default for SegmentFlights {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl SegmentFlights {
+    pub fn new(cache: Option<ProxyCache>) -> Self {
+        let shared_limit = cache.as_ref().map_or(MAX_SHARED_BYTES, |c| {
+            c.max_segment_size().min(MAX_SHARED_BYTES)
+        });
+        Self {
+            flights: Arc::default(),
+            cache,
+            shared_limit,
+        }
+    }
+
+    /// 加入 `url` 正在进行的请求，没有时通过 `start` 发起新的上游请求
+    pub async fn fetch<F>(&self, url: &str, start: F) -> Result<(SegmentHead, Body)>
+    where
+        F: Future<Output = Result<reqwest::Response>> + Send + 'static,
+    {
+        // 在持有请求表锁时注册读取方，请求不会在这之间停止共享
+        let (flight, reader, leader) = {
+            let mut flights = self.flights.lock();
+            match flights.get(url) {
+                Some(flight) => (flight.clone(), flight.reader(), false),
+                None => {
+                    let flight = Arc::new(Flight::new());
+                    flights.insert(url.to_string(), flight.clone());
+                    (flight.clone(), flight.reader(), true)
+                }
+            }
+        };
+
+        if leader {
+            tokio::spawn(self.clone().run(url.to_string(), flight.clone(), start));
+        } else {
+            debug!("Joining in-flight request: {}", url);
+        }
+
+        let head = flight.head().await?;
+        Ok((head, reader.body()))
+    }
+
+    /// 下载片段，完成后移除请求记录
+    async fn run<F>(self, url: String, flight: Arc<Flight>, start: F)
+    where
+        F: Future<Output = Result<reqwest::Response>>,
+    {
+        let completed = self.download(&url, &flight, start).await;
+        Self::remove(&mut self.flights.lock(), &url, &flight);
+
+        if completed {
+            flight.update(|state| state.done = true);
+        }
+    }
+
+    /// 移除请求记录（同一 URL 可能已经有了新的请求）
+    fn remove(flights: &mut HashMap<String, Arc<Flight>>, url: &str, flight: &Arc<Flight>) {
+        if flights.get(url).is_some_and(|f| Arc::ptr_eq(f, flight)) {
+            flights.remove(url);
+        }
+    }
+
+    /// 读取上游响应并写入请求状态，同时写入磁盘缓存；完整读取时返回 true
+    ///
+    /// 仍在共享时数据块完整保留，读取完成后写入内存缓存（先写入缓存再移除请求记录，
+    /// 之后到达的请求可以直接命中缓存）
+    async fn download<F>(&self, url: &str, flight: &Arc<Flight>, start: F) -> bool
+    where
+        F: Future<Output = Result<reqwest::Response>>,
+    {
+        let mut response = match start.await {
+            Ok(response) => response,
+            Err(e) => {
+                flight.update(|state| state.head = Some(Err(Arc::new(e))));
+                return false;
+            }
+        };
+
+        let head = SegmentHead {
+            content_type: response
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string()),
+            content_length: response.content_length(),
+        };
+        flight.update(|state| state.head = Some(Ok(head.clone())));
+
+        let mut writer = match &self.cache {
+            Some(cache) => {
+                cache
+                    .disk_writer(url, head.content_type.as_deref(), head.content_length)
+                    .await
+            }
+            None => None,
+        };
+
+        loop {
+            match response.chunk().await {
+                Ok(Some(chunk)) => {
+                    let exceeded = flight.update(|state| {
+                        state.push(chunk.clone());
+                        state.shared && state.received > self.shared_limit
+                    });
+                    if exceeded {
+                        debug!(
+                            "Segment {} exceeds {} bytes, no longer shared",
+                            url, self.shared_limit
+                        );
+                        // 持有请求表锁时停止共享，之后到达的请求会发起新的下载
+                        let mut flights = self.flights.lock();
+                        Self::remove(&mut flights, url, flight);
+                        flight.update(|state| {
+                            state.shared = false;
+                            state.trim();
+                        });
+                    }
+                    if let Some(current) = writer.as_mut()
+                        && !current.write(&chunk).await
+                        && let Some(failed) = writer.take()
+                    {
+                        failed.abort().await;
+                    }
+                    if !flight.wait_for_readers().await {
+                        debug!("All readers of {} left, stopping download", url);
+                        if let Some(writer) = writer {
+                            writer.abort().await;
+                        }
+                        return false;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("Failed to read segment {}: {}", url, e);
+                    flight.update(|state| state.error = Some(e.without_url().to_string()));
+                    if let Some(writer) = writer {
+                        writer.abort().await;
+                    }
+                    return false;
+                }
+            }
+        }
+
+        if let Some(writer) = writer {
+            writer.finish().await;
+        }
+
+        if let Some(cache) = &self.cache {
+            let body = {
+                let mut state = flight.state.lock();
+                state
+                    .shared
+                    .then(|| state.chunks.make_contiguous().concat())
+            };
+            if let Some(body) = body {
+                let segment = CachedSegment {
+                    content_type: head.content_type,
+                    body: Bytes::from(body),
+                };
+                cache.insert_segment(url.to_string(), segment).await;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    /// 分三次、每次间隔 20ms 返回数据的上游响应
+    fn slow_response() -> reqwest::Response {
+        let chunks = stream::unfold(0, |i| async move {
+            if i == 3 {
+                return None;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            Some((Ok::<_, std::io::Error>(Bytes::from(format!("chunk{};", i))), i + 1))
+        });
+        let response = axum::http::Response::builder()
+            .header("content-type", "video/mp2t")
+            .body(reqwest::Body::wrap_stream(chunks))
+            .unwrap();
+        reqwest::Response::from(response)
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_fetches_share_one_stream() {
+        let flights = SegmentFlights::new(None);
+        let started = Arc::new(AtomicUsize::new(0));
+        let url = "http://example.com/seg1.ts";
+
+        let fetch = || {
+            let started = started.clone();
+            let flights = flights.clone();
+            async move {
+                let (head, body) = flights
+                    .fetch(url, async move {
+                        started.fetch_add(1, Ordering::SeqCst);
+                        Ok(slow_response())
+                    })
+                    .await
+                    .unwrap();
+                let bytes = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+                (head, bytes)
+            }
+        };
+
+        let first = tokio::spawn(fetch());
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        let second = tokio::spawn(fetch());
+
+        let (head, first) = first.await.unwrap();
+        let (_, second) = second.await.unwrap();
+        assert_eq!(head.content_type.as_deref(), Some("video/mp2t"));
+        assert_eq!(first, "chunk0;chunk1;chunk2;");
+        assert_eq!(second, first);
+        assert_eq!(started.load(Ordering::SeqCst), 1);
+        assert!(flights.flights.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_large_segments_are_not_shared() {
+        let mut flights = SegmentFlights::new(None);
+        // 第二块到达后超过共享上限
+        flights.shared_limit = 10;
+        let started = Arc::new(AtomicUsize::new(0));
+        let url = "http://example.com/movie.mp4";
+
+        let fetch = || {
+            let started = started.clone();
+            let flights = flights.clone();
+            async move {
+                let (_, body) = flights
+                    .fetch(url, async move {
+                        started.fetch_add(1, Ordering::SeqCst);
+                        Ok(slow_response())
+                    })
+                    .await
+                    .unwrap();
+                axum::body::to_bytes(body, usize::MAX).await.unwrap()
+            }
+        };
+
+        let first = tokio::spawn(fetch());
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        // 仍在共享上限内，加入第一个请求
+        let second = tokio::spawn(fetch());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        // 已超过上限，单独下载
+        let third = tokio::spawn(fetch());
+
+        for body in [first, second, third] {
+            assert_eq!(body.await.unwrap(), "chunk0;chunk1;chunk2;");
+        }
+        assert_eq!(started.load(Ordering::SeqCst), 2);
+        assert!(flights.flights.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_unshared_download_stops_without_readers() {
+        let mut flights = SegmentFlights::new(None);
+        flights.shared_limit = 0;
+        let flight = Arc::new(Flight::new());
+        let reader = flight.reader();
+
+        let run = tokio::spawn(flights.clone().run(
+            "http://example.com/live.flv".to_string(),
+            flight.clone(),
+            async { Ok(slow_response()) },
+        ));
+        flight.head().await.unwrap();
+        drop(reader);
+        run.await.unwrap();
+
+        // 读取方离开后停止下载并释放已缓冲的数据
+        let state = flight.state.lock();
+        assert!(!state.done);
+        assert!(state.chunks.is_empty());
+        assert!(state.received < 21);
+    }
+}
@@ -0,0 +1,382 @@
+use moka::future::Cache;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use url::{Host, Url};
+
+use crate::error::{AppError, Result};
+use crate::services::ChannelManager;
+
+/// 代理目标被策略拒绝
+#[derive(Debug, Error)]
+#[error("{0}")]
+pub struct PolicyViolation(String);
+
+/// 代理目标地址策略（防止 SSRF）
+///
+/// - 只允许 http / https
+/// - 主机必须是已加载频道的地址（含备用和回看地址）、配置的额外主机，
+///   或这些地址返回的播放列表中引用的主机
+/// - 端口必须在允许列表中，或与频道地址的端口一致
+/// - 解析后（包括每次重定向）的 IP 不能是回环、内网、链路本地等地址；
+///   频道地址和配置的主机由管理员指定，不受此限制
+///
+/// 订阅、EPG 和健康检查等内部请求只检查解析后的 IP（`check_fetch`），不要求主机在白名单中
+pub struct UrlPolicy {
+    channel_manager: Arc<ChannelManager>,
+    allowed_hosts: HashSet<String>,
+    allowed_ports: HashSet<u16>,
+    /// 配置的订阅和 EPG 地址的主机
+    configured_hosts: HashSet<String>,
+    /// 播放列表中引用的 `host:port`
+    learned: Cache<String, ()>,
+}
+
+impl UrlPolicy {
+    /// 创建策略，`allowed_hosts` 为额外允许的主机（`host` 或 `host:port`）
+    pub fn new(
+        channel_manager: Arc<ChannelManager>,
+        allowed_hosts: &[String],
+        allowed_ports: &[u16],
+    ) -> Self {
+        Self {
+            channel_manager,
+            allowed_hosts: allowed_hosts.iter().map(|h| h.to_lowercase()).collect(),
+            allowed_ports: allowed_ports.iter().copied().collect(),
+            configured_hosts: HashSet::new(),
+            learned: Cache::builder()
+                .max_capacity(10_000)
+                .time_to_idle(Duration::from_secs(86400))
+                .build(),
+        }
+    }
+
+    /// 信任配置的订阅和 EPG 地址的主机，允许内部请求访问内网地址
+    pub fn with_trusted_urls<'a>(mut self, urls: impl IntoIterator<Item = &'a str>) -> Self {
+        self.configured_hosts.extend(
+            urls.into_iter()
+                .filter_map(|url| Url::parse(url).ok())
+                .filter_map(|url| url.host_str().map(str::to_lowercase)),
+        );
+        self
+    }
+
+    /// 检查代理请求的目标地址
+    pub fn check(&self, url: &str) -> Result<()> {
+        let parsed = Url::parse(url)
+            .map_err(|e| AppError::Forbidden(format!("Invalid proxy target: {}", e)))?;
+        let (host, port) = self
+            .check_target(&parsed)
+            .map_err(|e| AppError::Forbidden(e.to_string()))?;
+
+        if !self.is_allowed_host(&host, port) {
+            return Err(AppError::Forbidden(format!(
+                "{}:{} is not a known channel origin",
+                host, port
+            )));
+        }
+        Ok(())
+    }
+
+    /// 检查重定向目标：重定向常指向 CDN，不要求在主机白名单中，解析后的 IP 由 `PolicyResolver` 检查
+    pub fn check_redirect(&self, url: &Url) -> std::result::Result<(), PolicyViolation> {
+        self.check_target(url).map(|_| ())
+    }
+
+    /// 检查内部请求（及其重定向）的目标：只限制协议和 IP 字面量，域名的解析结果由 `PolicyResolver` 检查
+    pub fn check_fetch(&self, url: &Url) -> std::result::Result<(), PolicyViolation> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(PolicyViolation(format!(
+                "Scheme {} is not allowed",
+                url.scheme()
+            )));
+        }
+        let host = url
+            .host_str()
+            .ok_or_else(|| PolicyViolation("Target has no host".to_string()))?;
+        self.check_ip_literal(url, host)
+    }
+
+    /// 记录从允许的地址获取的播放列表中引用的主机
+    pub async fn learn(&self, urls: impl IntoIterator<Item = String>) {
+        for url in urls {
+            if let Ok(url) = Url::parse(&url)
+                && let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default())
+            {
+                self.learned.insert(format!("{}:{}", host, port), ()).await;
+            }
+        }
+    }
+
+    /// 检查协议、端口和 IP 字面量，返回主机名和端口
+    fn check_target(&self, url: &Url) -> std::result::Result<(String, u16), PolicyViolation> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(PolicyViolation(format!(
+                "Scheme {} is not allowed",
+                url.scheme()
+            )));
+        }
+
+        let host = url
+            .host_str()
+            .ok_or_else(|| PolicyViolation("Proxy target has no host".to_string()))?
+            .to_string();
+        let port = url
+            .port_or_known_default()
+            .ok_or_else(|| PolicyViolation("Proxy target has no port".to_string()))?;
+
+        if !self.is_allowed_port(&host, port) {
+            return Err(PolicyViolation(format!("Port {} is not allowed", port)));
+        }
+
+        self.check_ip_literal(url, &host)?;
+        Ok((host, port))
+    }
+
+    /// IP 字面量不经过 DNS 解析，直接检查
+    fn check_ip_literal(&self, url: &Url, host: &str) -> std::result::Result<(), PolicyViolation> {
+        let ip = match url.host() {
+            Some(Host::Ipv4(ip)) => Some(IpAddr::V4(ip)),
+            Some(Host::Ipv6(ip)) => Some(IpAddr::V6(ip)),
+            _ => None,
+        };
+        if let Some(ip) = ip
+            && is_denied_ip(ip)
+            && !self.is_trusted_host(host)
+        {
+            return Err(PolicyViolation(format!("Address {} is not allowed", ip)));
+        }
+        Ok(())
+    }
+
+    fn is_allowed_host(&self, host: &str, port: u16) -> bool {
+        let origin = format!("{}:{}", host, port);
+        self.channel_manager.is_channel_origin(host, Some(port))
+            || self.allowed_hosts.contains(host)
+            || self.allowed_hosts.contains(&origin)
+            || self.learned.contains_key(&origin)
+    }
+
+    fn is_allowed_port(&self, host: &str, port: u16) -> bool {
+        self.allowed_ports.contains(&port) || self.is_allowed_host(host, port)
+    }
+
+    /// 由管理员指定的主机（频道地址、配置的额外主机、订阅和 EPG 地址），允许解析到内网地址
+    fn is_trusted_host(&self, host: &str) -> bool {
+        self.channel_manager.is_channel_origin(host, None)
+            || self.configured_hosts.contains(host)
+            || self.allowed_hosts.contains(host)
+            || self
+                .allowed_hosts
+                .iter()
+                .any(|h| h.rsplit_once(':').is_some_and(|(name, _)| name == host))
+    }
+}
+
+/// 检查解析结果的 DNS 解析器，重定向后的每次连接也会经过这里
+pub struct PolicyResolver {
+    policy: Arc<UrlPolicy>,
+}
+
+impl PolicyResolver {
+    pub fn new(policy: Arc<UrlPolicy>) -> Self {
+        Self { policy }
+    }
+}
+
+impl Resolve for PolicyResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let policy = self.policy.clone();
+        let host = name.as_str().to_string();
+
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .collect();
+
+            if !policy.is_trusted_host(&host)
+                && let Some(addr) = addrs.iter().find(|addr| is_denied_ip(addr.ip()))
+            {
+                return Err(Box::new(PolicyViolation(format!(
+                    "{} resolves to a denied address {}",
+                    host,
+                    addr.ip()
+                ))) as Box<dyn std::error::Error + Send + Sync>);
+            }
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// 是否为不允许代理访问的地址（回环、内网、链路本地、组播、保留地址等）
+pub fn is_denied_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_denied_ipv4(ip),
+        IpAddr::V6(ip) => match embedded_ipv4(ip) {
+            Some(ip) => is_denied_ipv4(ip),
+            None => is_denied_ipv6(ip),
+        },
+    }
+}
+
+/// IPv6 地址中嵌入的 IPv4 地址：IPv4 映射（`::ffff:a.b.c.d`）、IPv4 兼容（`::a.b.c.d`）、
+/// NAT64（`64:ff9b::/96`）和 6to4（`2002::/16`），这些地址最终会访问到对应的 IPv4 地址
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let o = ip.octets();
+    let tail = Ipv4Addr::new(o[12], o[13], o[14], o[15]);
+    match ip.segments() {
+        [0, 0, 0, 0, 0, 0xffff, _, _] => Some(tail),
+        // `::` 和 `::1` 按 IPv6 地址处理
+        [0, 0, 0, 0, 0, 0, _, _] if !ip.is_unspecified() && !ip.is_loopback() => Some(tail),
+        [0x64, 0xff9b, 0, 0, 0, 0, _, _] => Some(tail),
+        [0x2002, ..] => Some(Ipv4Addr::new(o[2], o[3], o[4], o[5])),
+        _ => None,
+    }
+}
+
+fn is_denied_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        || a == 0
+        // 100.64.0.0/10 运营商级 NAT
+        || (a == 100 && (64..128).contains(&b))
+        // 192.0.0.0/24 IETF 协议分配
+        || (a == 192 && b == 0 && c == 0)
+        // 198.18.0.0/15 基准测试
+        || (a == 198 && (b == 18 || b == 19))
+        // 240.0.0.0/4 保留
+        || a >= 240
+}
+
+fn is_denied_ipv6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // fc00::/7 唯一本地地址
+        || (first & 0xfe00) == 0xfc00
+        // fe80::/10 链路本地地址
+        || (first & 0xffc0) == 0xfe80
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Channel;
+
+    fn policy() -> UrlPolicy {
+        let manager = Arc::new(ChannelManager::new());
+        manager.register_source("default", "test.m3u");
+        manager
+            .update_source(
+                "default",
+                vec![
+                    Channel {
+                        id: "ch_1".to_string(),
+                        url: "http://live.example.com:8000/ch1/index.m3u8".to_string(),
+                        ..Default::default()
+                    },
+                    Channel {
+                        id: "ch_2".to_string(),
+                        url: "http://192.168.1.10/ch2.m3u8".to_string(),
+                        ..Default::default()
+                    },
+                ],
+            )
+            .unwrap();
+
+        UrlPolicy::new(manager, &["cdn.example.net".to_string()], &[80, 443])
+    }
+
+    #[tokio::test]
+    async fn test_url_policy() {
+        let policy = policy();
+
+        // 频道地址（包括管理员配置的内网地址）和额外主机
+        assert!(policy.check("http://live.example.com:8000/ch1/seg1.ts").is_ok());
+        assert!(policy.check("http://192.168.1.10/ch2/seg1.ts").is_ok());
+        assert!(policy.check("https://cdn.example.net/seg1.ts").is_ok());
+
+        // 协议、端口、未知主机和内网地址
+        assert!(policy.check("file:///etc/passwd").is_err());
+        assert!(policy.check("http://live.example.com:22/").is_err());
+        assert!(policy.check("http://evil.example.org/").is_err());
+        assert!(policy.check("http://127.0.0.1:8006/api/channels").is_err());
+        assert!(policy.check("http://169.254.169.254/latest/meta-data/").is_err());
+        assert!(policy.check("http://[::ffff:10.0.0.1]/").is_err());
+
+        // 播放列表引用的主机
+        assert!(policy.check("https://edge.example.org/seg1.ts").is_err());
+        policy
+            .learn(vec!["https://edge.example.org/seg1.ts".to_string()])
+            .await;
+        assert!(policy.check("https://edge.example.org/seg2.ts").is_ok());
+
+        // 重定向不要求主机白名单，但仍然检查协议、端口和 IP
+        let redirect = Url::parse("https://other.example.org/seg1.ts").unwrap();
+        assert!(policy.check_redirect(&redirect).is_ok());
+        let redirect = Url::parse("http://10.0.0.1/seg1.ts").unwrap();
+        assert!(policy.check_redirect(&redirect).is_err());
+    }
+
+    #[test]
+    fn test_check_fetch() {
+        let policy = policy().with_trusted_urls(["http://10.0.0.5:8080/epg.xml"]);
+        let check = |url: &str| policy.check_fetch(&Url::parse(url).unwrap());
+
+        // 内部请求不限制主机和端口
+        assert!(check("http://epg.example.org:8080/epg.xml").is_ok());
+        // 配置的订阅和 EPG 主机、频道地址可以是内网地址
+        assert!(check("http://10.0.0.5:8080/epg.xml").is_ok());
+        assert!(check("http://192.168.1.10/ch2/index.m3u8").is_ok());
+        // 远程播放列表头部或子播放列表中的内网地址
+        assert!(check("http://169.254.169.254/latest/meta-data/").is_err());
+        assert!(check("http://127.0.0.1:8006/api/channels").is_err());
+        assert!(check("file:///etc/passwd").is_err());
+    }
+
+    #[test]
+    fn test_denied_ips() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.0.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::127.0.0.1",
+            "::a9fe:a9fe",
+            "64:ff9b::a9fe:a9fe",
+            "64:ff9b::10.0.0.1",
+            "2002:c0a8:0101::1",
+            "2002:7f00:1::",
+        ] {
+            assert!(is_denied_ip(ip.parse().unwrap()), "{} should be denied", ip);
+        }
+        for ip in [
+            "8.8.8.8",
+            "203.0.114.1",
+            "2606:4700::1111",
+            "::ffff:8.8.8.8",
+            "64:ff9b::808:808",
+            "2002:808:808::1",
+        ] {
+            assert!(!is_denied_ip(ip.parse().unwrap()), "{} should be allowed", ip);
+        }
+    }
+}
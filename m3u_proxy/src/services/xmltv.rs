@@ -0,0 +1,292 @@
+use chrono::{DateTime, NaiveDateTime};
+use flate2::read::GzDecoder;
+use quick_xml::Reader;
+use quick_xml::events::{BytesStart, Event};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+
+use crate::error::{AppError, Result};
+use crate::models::{EpgChannel, Programme};
+
+/// XMLTV 解析结果
+#[derive(Debug, Default)]
+pub struct XmltvData {
+    pub channels: Vec<EpgChannel>,
+    pub programmes: Vec<Programme>,
+}
+
+/// 当前正在读取文本的元素
+#[derive(Clone, Copy, PartialEq)]
+enum TextField {
+    DisplayName,
+    Title,
+    SubTitle,
+    Description,
+    Category,
+}
+
+/// XMLTV 解析器
+pub struct XmltvParser;
+
+impl XmltvParser {
+    /// 解析 XMLTV 数据，自动识别 gzip 压缩
+    pub fn parse_bytes(bytes: &[u8]) -> Result<XmltvData> {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            Self::parse_reader(BufReader::new(GzDecoder::new(bytes)))
+        } else {
+            Self::parse_reader(bytes)
+        }
+    }
+
+    /// 以流式方式解析 XMLTV
+    pub fn parse_reader<R: BufRead>(reader: R) -> Result<XmltvData> {
+        let mut reader = Reader::from_reader(reader);
+        reader.config_mut().trim_text(true);
+
+        let mut data = XmltvData::default();
+        let mut buf = Vec::new();
+        let mut channel: Option<EpgChannel> = None;
+        let mut programme: Option<Programme> = None;
+        let mut field: Option<TextField> = None;
+        let mut text = String::new();
+
+        loop {
+            let event = reader.read_event_into(&mut buf).map_err(|e| {
+                AppError::InvalidEpg(format!(
+                    "XML error at position {}: {}",
+                    reader.buffer_position(),
+                    e
+                ))
+            })?;
+
+            match event {
+                Event::Start(ref e) | Event::Empty(ref e) => {
+                    let is_empty = matches!(event, Event::Empty(_));
+                    match e.local_name().as_ref() {
+                        b"channel" if programme.is_none() => {
+                            channel = Some(EpgChannel {
+                                id: attribute(e, b"id").unwrap_or_default(),
+                                ..Default::default()
+                            });
+                        }
+                        b"programme" => {
+                            programme = Self::start_programme(e);
+                        }
+                        b"icon" => {
+                            let src = attribute(e, b"src");
+                            if let Some(programme) = programme.as_mut() {
+                                programme.icon = programme.icon.take().or(src);
+                            } else if let Some(channel) = channel.as_mut() {
+                                channel.icon = channel.icon.take().or(src);
+                            }
+                        }
+                        name if !is_empty => {
+                            field = match name {
+                                b"display-name" if channel.is_some() => {
+                                    Some(TextField::DisplayName)
+                                }
+                                b"title" if programme.is_some() => Some(TextField::Title),
+                                b"sub-title" if programme.is_some() => Some(TextField::SubTitle),
+                                b"desc" if programme.is_some() => Some(TextField::Description),
+                                b"category" if programme.is_some() => Some(TextField::Category),
+                                _ => None,
+                            };
+                            text.clear();
+                        }
+                        _ => {}
+                    }
+                }
+                Event::Text(e) if field.is_some() => {
+                    let value = e
+                        .unescape()
+                        .map_err(|e| AppError::InvalidEpg(format!("Invalid text: {}", e)))?;
+                    text.push_str(&value);
+                }
+                Event::CData(e) if field.is_some() => {
+                    text.push_str(&String::from_utf8_lossy(&e));
+                }
+                Event::End(ref e) => match e.local_name().as_ref() {
+                    b"channel" if programme.is_none() => {
+                        if let Some(channel) = channel.take()
+                            && !channel.id.is_empty()
+                        {
+                            data.channels.push(channel);
+                        }
+                    }
+                    b"programme" => {
+                        if let Some(programme) = programme.take() {
+                            data.programmes.push(programme);
+                        }
+                    }
+                    _ => {
+                        if let Some(current) = field.take() {
+                            let value = std::mem::take(&mut text);
+                            Self::apply_text(current, value, channel.as_mut(), programme.as_mut());
+                        }
+                    }
+                },
+                Event::Eof => break,
+                _ => {}
+            }
+
+            buf.clear();
+        }
+
+        Self::fill_missing_stop(&mut data.programmes);
+
+        Ok(data)
+    }
+
+    /// 解析 `<programme>` 的属性，缺少频道或开始时间时忽略该节目
+    fn start_programme(e: &BytesStart) -> Option<Programme> {
+        let channel = attribute(e, b"channel").filter(|c| !c.is_empty())?;
+        let start = attribute(e, b"start").and_then(|s| Self::parse_time(&s))?;
+        let stop = attribute(e, b"stop")
+            .and_then(|s| Self::parse_time(&s))
+            .unwrap_or(start);
+
+        Some(Programme {
+            channel,
+            start,
+            stop,
+            ..Default::default()
+        })
+    }
+
+    fn apply_text(
+        field: TextField,
+        value: String,
+        channel: Option<&mut EpgChannel>,
+        programme: Option<&mut Programme>,
+    ) {
+        if value.is_empty() {
+            return;
+        }
+
+        match (field, channel, programme) {
+            (TextField::DisplayName, Some(channel), _) => channel.display_names.push(value),
+            (TextField::Title, _, Some(p)) if p.title.is_empty() => p.title = value,
+            (TextField::SubTitle, _, Some(p)) if p.sub_title.is_none() => p.sub_title = Some(value),
+            (TextField::Description, _, Some(p)) if p.description.is_none() => {
+                p.description = Some(value)
+            }
+            (TextField::Category, _, Some(p)) => p.categories.push(value),
+            _ => {}
+        }
+    }
+
+    /// 没有 stop 属性的节目以同频道下一个节目的开始时间作为结束时间
+    fn fill_missing_stop(programmes: &mut [Programme]) {
+        let mut next_start: HashMap<String, i64> = HashMap::new();
+        let mut order: Vec<usize> = (0..programmes.len()).collect();
+        order.sort_by_key(|&i| std::cmp::Reverse(programmes[i].start));
+
+        for i in order {
+            let programme = &mut programmes[i];
+            if programme.stop <= programme.start
+                && let Some(&next) = next_start.get(&programme.channel)
+            {
+                programme.stop = next;
+            }
+            next_start.insert(programme.channel.clone(), programme.start);
+        }
+    }
+
+    /// 解析 XMLTV 时间（`20250115203000 +0800`），没有时区时按 UTC 处理
+    pub fn parse_time(value: &str) -> Option<i64> {
+        let value = value.trim();
+        if let Ok(time) = DateTime::parse_from_str(value, "%Y%m%d%H%M%S %z") {
+            return Some(time.timestamp());
+        }
+
+        let digits: String = value.chars().take_while(|c| c.is_ascii_digit()).collect();
+        let padded = format!("{:0<14}", digits);
+        NaiveDateTime::parse_from_str(&padded[..14], "%Y%m%d%H%M%S")
+            .ok()
+            .map(|time| time.and_utc().timestamp())
+    }
+}
+
+/// 读取并反转义属性值
+fn attribute(e: &BytesStart, name: &[u8]) -> Option<String> {
+    e.try_get_attribute(name)
+        .ok()
+        .flatten()
+        .and_then(|attr| attr.unescape_value().ok().map(|v| v.into_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    const SAMPLE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<tv generator-info-name="test">
+  <channel id="CCTV1">
+    <display-name lang="zh">CCTV-1 综合</display-name>
+    <display-name>CCTV1</display-name>
+    <icon src="https://example.com/cctv1.png"/>
+  </channel>
+  <programme start="20250115200000 +0800" stop="20250115203000 +0800" channel="CCTV1">
+    <title lang="zh">新闻联播</title>
+    <desc>每日新闻 &amp; 评论</desc>
+    <category>新闻</category>
+  </programme>
+  <programme start="20250115203000 +0800" channel="CCTV1">
+    <title><![CDATA[焦点访谈]]></title>
+  </programme>
+  <programme start="20250115210000 +0800" stop="20250115220000 +0800" channel="CCTV1">
+    <title>电视剧</title>
+  </programme>
+</tv>"#;
+
+    #[test]
+    fn test_parse_xmltv() {
+        let data = XmltvParser::parse_bytes(SAMPLE.as_bytes()).unwrap();
+
+        assert_eq!(data.channels.len(), 1);
+        assert_eq!(data.channels[0].display_names, vec!["CCTV-1 综合", "CCTV1"]);
+        assert_eq!(
+            data.channels[0].icon.as_deref(),
+            Some("https://example.com/cctv1.png")
+        );
+
+        assert_eq!(data.programmes.len(), 3);
+        assert_eq!(data.programmes[0].title, "新闻联播");
+        assert_eq!(data.programmes[0].start, 1736942400);
+        assert_eq!(
+            data.programmes[0].description.as_deref(),
+            Some("每日新闻 & 评论")
+        );
+        assert_eq!(data.programmes[0].categories, vec!["新闻"]);
+
+        assert_eq!(data.programmes[1].title, "焦点访谈");
+        assert_eq!(data.programmes[1].stop, data.programmes[2].start);
+    }
+
+    #[test]
+    fn test_parse_gzip_xmltv() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(SAMPLE.as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let data = XmltvParser::parse_bytes(&compressed).unwrap();
+        assert_eq!(data.programmes.len(), 3);
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(
+            XmltvParser::parse_time("20250115120000 +0000"),
+            Some(1736942400)
+        );
+        assert_eq!(
+            XmltvParser::parse_time("20250115200000 +0800"),
+            Some(1736942400)
+        );
+        assert_eq!(XmltvParser::parse_time("20250115120000"), Some(1736942400));
+        assert_eq!(XmltvParser::parse_time("invalid"), None);
+    }
+}
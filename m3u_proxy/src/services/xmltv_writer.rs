@@ -0,0 +1,139 @@
+use chrono::DateTime;
+use quick_xml::escape::escape;
+use std::fmt::Write;
+
+use crate::models::{Channel, Programme};
+
+/// 导出用的单个频道节目单
+pub struct ChannelGuide {
+    pub channel: Channel,
+    /// 频道图标（优先使用播放列表中的 tvg-logo，其次使用 XMLTV 中的图标）
+    pub icon: Option<String>,
+    pub programmes: Vec<Programme>,
+}
+
+/// XMLTV 生成器
+///
+/// 把 EPG 索引重新输出为 XMLTV，频道 ID 使用本服务的稳定 ID，与导出的 M3U 中的 tvg-id 对应
+pub struct XmltvWriter;
+
+impl XmltvWriter {
+    /// 生成 XMLTV 内容
+    pub fn write(guides: &[ChannelGuide]) -> String {
+        let mut output = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <!DOCTYPE tv SYSTEM \"xmltv.dtd\">\n\
+             <tv generator-info-name=\"m3u_proxy\">\n",
+        );
+
+        for guide in guides {
+            let channel = &guide.channel;
+            let _ = writeln!(output, "  <channel id=\"{}\">", escape(channel.id.as_str()));
+            write_element(&mut output, 4, "display-name", &channel.name);
+            if let Some(tvg_name) = &channel.tvg_name
+                && tvg_name != &channel.name
+            {
+                write_element(&mut output, 4, "display-name", tvg_name);
+            }
+            if let Some(icon) = &guide.icon {
+                let _ = writeln!(output, "    <icon src=\"{}\"/>", escape(icon.as_str()));
+            }
+            output.push_str("  </channel>\n");
+        }
+
+        for guide in guides {
+            for programme in &guide.programmes {
+                write_programme(&mut output, &guide.channel.id, programme);
+            }
+        }
+
+        output.push_str("</tv>\n");
+        output
+    }
+}
+
+fn write_programme(output: &mut String, channel_id: &str, programme: &Programme) {
+    let _ = writeln!(
+        output,
+        "  <programme start=\"{}\" stop=\"{}\" channel=\"{}\">",
+        format_time(programme.start),
+        format_time(programme.stop),
+        escape(channel_id)
+    );
+    write_element(output, 4, "title", &programme.title);
+    if let Some(sub_title) = &programme.sub_title {
+        write_element(output, 4, "sub-title", sub_title);
+    }
+    if let Some(description) = &programme.description {
+        write_element(output, 4, "desc", description);
+    }
+    for category in &programme.categories {
+        write_element(output, 4, "category", category);
+    }
+    if let Some(icon) = &programme.icon {
+        let _ = writeln!(output, "    <icon src=\"{}\"/>", escape(icon.as_str()));
+    }
+    output.push_str("  </programme>\n");
+}
+
+/// 写入 `<name>text</name>`
+fn write_element(output: &mut String, indent: usize, name: &str, text: &str) {
+    let _ = writeln!(
+        output,
+        "{:indent$}<{name}>{}</{name}>",
+        "",
+        escape(text),
+        indent = indent,
+        name = name
+    );
+}
+
+/// XMLTV 时间格式（统一输出为 UTC）
+fn format_time(timestamp: i64) -> String {
+    DateTime::from_timestamp(timestamp, 0)
+        .map(|time| time.format("%Y%m%d%H%M%S +0000").to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::xmltv::XmltvParser;
+
+    #[test]
+    fn test_write_xmltv() {
+        let guides = vec![ChannelGuide {
+            channel: Channel {
+                id: "ch_1".to_string(),
+                tvg_id: "CCTV1".to_string(),
+                name: "CCTV-1 <综合>".to_string(),
+                ..Default::default()
+            },
+            icon: Some("https://example.com/a.png?x=1&y=2".to_string()),
+            programmes: vec![Programme {
+                channel: "CCTV1".to_string(),
+                start: 1736942400,
+                stop: 1736944200,
+                title: "新闻 & 天气".to_string(),
+                categories: vec!["新闻".to_string()],
+                ..Default::default()
+            }],
+        }];
+
+        let output = XmltvWriter::write(&guides);
+        assert!(output.contains(
+            r#"<programme start="20250115120000 +0000" stop="20250115123000 +0000" channel="ch_1">"#
+        ));
+
+        let data = XmltvParser::parse_bytes(output.as_bytes()).unwrap();
+        assert_eq!(data.channels[0].id, "ch_1");
+        assert_eq!(data.channels[0].display_names, vec!["CCTV-1 <综合>"]);
+        assert_eq!(
+            data.channels[0].icon.as_deref(),
+            Some("https://example.com/a.png?x=1&y=2")
+        );
+        assert_eq!(data.programmes[0].channel, "ch_1");
+        assert_eq!(data.programmes[0].title, "新闻 & 天气");
+        assert_eq!(data.programmes[0].start, 1736942400);
+    }
+}
@@ -2,11 +2,19 @@
 export interface Channel {
   id: string;
   tvg_id: string;
+  tvg_name?: string;
   name: string;
   logo?: string;
   group: string;
-  url: string;
   stream_type: 'HLS' | 'MP4' | 'FLV' | 'Other';
+  source: string;
+  // 非管理员只返回 tvg-chno、tvg-shift、catchup 等不含地址的属性
+  attributes: Record<string, string>;
+  // 以下字段包含上游信息，非管理员为空，播放地址通过 /api/play 获取
+  url: string;
+  backup_urls: string[];
+  vlc_options: Record<string, string>;
+  kodi_props: Record<string, string>;
 }
 
 // 频道列表响应
@@ -28,7 +36,9 @@ export interface PlayInfo {
   group: string;
   stream_type: string;
   play_url: string;
-  original_url: string;
+  // 仅管理员可见
+  original_url?: string;
+  backup_urls?: string[];
 }
 
 // API 查询参数